@@ -11,6 +11,16 @@ impl BoundingBox {
         BoundingBox { min, max }
     }
 
+    // Smallest box around the points, padded so flat shapes lying in an axis plane still have a box with
+    // some thickness to hit
+    pub fn from_points(points: &[Vec3]) -> BoundingBox {
+        let padding = Vec3::new(1e-4, 1e-4, 1e-4);
+        let bbox = points.iter().fold(BoundingBox::new(points[0], points[0]), |bbox, point| {
+            BoundingBox::containing_box(&bbox, &BoundingBox::new(*point, *point))
+        });
+        BoundingBox::new(bbox.min - padding, bbox.max + padding)
+    }
+
     pub fn containing_box(box0: &BoundingBox, box1: &BoundingBox) -> BoundingBox {
         let min = Vec3::new(
             box0.min.x().min(box1.min.x()),
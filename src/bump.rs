@@ -0,0 +1,115 @@
+// Fine surface detail without extra geometry, from tilting the normal a material shades with. The
+// geometric normal is left alone, so which side of the surface a ray is on and where rays leave from
+// still follow the actual surface.
+use crate::{
+    material::{BsdfSample, Material},
+    medium::Medium,
+    object::HitRecord,
+    sampler::Sampler,
+    texture::Texture,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Step in surface coordinates for estimating how a height texture changes
+const HEIGHT_DELTA: f32 = 0.0005;
+
+#[derive(Clone)]
+pub enum NormalPerturbation {
+    // Tangent space normal map, whose red, green and blue map [0, 1] to [-1, 1] along dpdu, the
+    // bitangent and the normal
+    NormalMap(Arc<dyn Texture>),
+    // Displacement along the normal by the texture's value times scale, in scene units
+    Bump { height: Arc<dyn Texture>, scale: f32 },
+}
+
+impl NormalPerturbation {
+    // Shading normal at a hit, on the same side as the surface's normal. Surfaces without a tangent
+    // frame keep their normal.
+    fn normal(&self, hit_record: &HitRecord) -> Vec3 {
+        let normal = hit_record.normal;
+        let shading_normal = match self {
+            NormalPerturbation::NormalMap(texture) => {
+                let tangent = hit_record.dpdu - normal.scalar_mul(normal.dot(&hit_record.dpdu));
+                if tangent.squared_length() == 0.0 {
+                    return normal;
+                }
+                let value = texture.value(hit_record).scalar_mul(2.0) - Vec3::new(1.0, 1.0, 1.0);
+                hit_record.shading_frame().local(&value)
+            }
+            NormalPerturbation::Bump { height, scale } => {
+                let height_at = |du: f32, dv: f32| {
+                    let mut shifted = *hit_record;
+                    shifted.point = hit_record.point + hit_record.dpdu.scalar_mul(du) + hit_record.dpdv.scalar_mul(dv);
+                    shifted.uv = (hit_record.uv.0 + du, hit_record.uv.1 + dv);
+                    height.scalar(&shifted) * scale
+                };
+                let base = height_at(0.0, 0.0);
+                let dpdu = hit_record.dpdu + normal.scalar_mul((height_at(HEIGHT_DELTA, 0.0) - base) / HEIGHT_DELTA);
+                let dpdv = hit_record.dpdv + normal.scalar_mul((height_at(0.0, HEIGHT_DELTA) - base) / HEIGHT_DELTA);
+                dpdu.cross(&dpdv)
+            }
+        };
+        if shading_normal.squared_length() == 0.0 || !shading_normal.x().is_finite() {
+            return normal;
+        }
+        let shading_normal = shading_normal.normalize();
+        if shading_normal.dot(&normal) < 0.0 {
+            -shading_normal
+        } else {
+            shading_normal
+        }
+    }
+}
+
+// Material shaded with a perturbed normal
+pub struct ShadingNormal {
+    base: Box<dyn Material>,
+    perturbation: NormalPerturbation,
+}
+
+impl ShadingNormal {
+    pub fn new(base: Box<dyn Material>, perturbation: NormalPerturbation) -> Self {
+        ShadingNormal { base, perturbation }
+    }
+
+    fn shade<'a>(&self, hit_record: &HitRecord<'a>) -> HitRecord<'a> {
+        let mut shaded = *hit_record;
+        shaded.normal = self.perturbation.normal(hit_record);
+        shaded
+    }
+}
+
+impl Material for ShadingNormal {
+    fn eval(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        self.base.eval(&self.shade(hit_record), wo, wi)
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        self.base.sample(&self.shade(hit_record), wo, sampler)
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> f32 {
+        self.base.pdf(&self.shade(hit_record), wo, wi)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+
+    fn emitted(&self, hit_record: &HitRecord, wo: &Vec3) -> Vec3 {
+        self.base.emitted(hit_record, wo)
+    }
+
+    fn transmittance(&self, hit_record: &HitRecord, distance: f32) -> Vec3 {
+        self.base.transmittance(hit_record, distance)
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        self.base.medium()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
@@ -4,7 +4,7 @@ use crate::{
     ray::Ray,
 };
 
-use rand::{thread_rng, Rng};
+use rand::{Rng, RngCore};
 
 pub struct BvhNode {
     left: Box<dyn Object>,
@@ -17,22 +17,20 @@ impl Object for BvhNode {
         Some(&self.bbox)
     }
 
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         if self.bbox.hit(ray, t_min, t_max) {
             let left_rec = self.left.hit(ray, t_min, t_max);
             let right_rec = self.right.hit(ray, t_min, t_max);
-            if left_rec.is_some() && right_rec.is_some() {
-                if left_rec.as_ref().unwrap().t < right_rec.as_ref().unwrap().t {
-                    left_rec
-                } else {
-                    right_rec
+            match (left_rec, right_rec) {
+                (Some(left_rec), Some(right_rec)) => {
+                    if left_rec.t < right_rec.t {
+                        Some(left_rec)
+                    } else {
+                        Some(right_rec)
+                    }
                 }
-            } else if left_rec.is_some() {
-                left_rec
-            } else if right_rec.is_some() {
-                right_rec
-            } else {
-                None
+                (left_rec, None) => left_rec,
+                (None, right_rec) => right_rec,
             }
         } else {
             None
@@ -41,8 +39,8 @@ impl Object for BvhNode {
 }
 
 impl BvhNode {
-    pub fn new(mut objects: Vec<Box<dyn Object>>) -> Self {
-        let axis_index = thread_rng().gen_range(0, 3);
+    pub fn new(mut objects: Vec<Box<dyn Object>>, rng: &mut dyn RngCore) -> Self {
+        let axis_index = rng.gen_range(0, 3);
         objects.sort_unstable_by(|a, b| {
             a.bounding_box().unwrap().min.data()[axis_index]
                 .partial_cmp(&b.bounding_box().unwrap().min.data()[axis_index])
@@ -56,18 +54,18 @@ impl BvhNode {
             (left, right)
         } else if objects.len() == 3 {
             let right = objects.pop().unwrap();
-            (Box::new(BvhNode::new(objects)), right)
+            (Box::new(BvhNode::new(objects, rng)), right)
         } else {
             let right_half = objects.split_off(objects.len() / 2);
             (
-                Box::new(BvhNode::new(objects)),
-                Box::new(BvhNode::new(right_half)),
+                Box::new(BvhNode::new(objects, rng)),
+                Box::new(BvhNode::new(right_half, rng)),
             )
         };
 
         let bbox = BoundingBox::containing_box(
-            &left.bounding_box().unwrap(),
-            &right.bounding_box().unwrap(),
+            left.bounding_box().unwrap(),
+            right.bounding_box().unwrap(),
         );
 
         BvhNode { left, right, bbox }
@@ -1,7 +1,7 @@
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::utils::random_in_unit_disk;
 use crate::vec3::Vec3;
-use rand::{thread_rng, Rng};
 
 #[derive(Debug)]
 pub struct Camera {
@@ -11,14 +11,59 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f32,
     open_time: f32,
     close_time: f32,
 }
 
+// Everything about the camera except the image aspect ratio, which comes from the render settings
+#[derive(Clone, Copy, Debug)]
+pub struct CameraSettings {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub v_up: Vec3,
+    // Top to bottom in radians
+    pub v_fov: f32,
+    pub aperture: f32,
+    pub focus_dist: f32,
+    pub open_time: f32,
+    pub close_time: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            look_from: Vec3::new(13.0, 2.0, 3.0),
+            look_at: Vec3::new(0.0, 0.0, 0.0),
+            v_up: Vec3::new(0.0, 1.0, 0.0),
+            v_fov: std::f32::consts::PI / 9.0,
+            aperture: 0.1,
+            focus_dist: 10.0,
+            open_time: 0.0,
+            close_time: 1.0,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect: f32) -> Camera {
+        Camera::new(
+            self.look_from,
+            self.look_at,
+            self.v_up,
+            self.v_fov,
+            aspect,
+            self.aperture,
+            self.focus_dist,
+            self.open_time,
+            self.close_time,
+        )
+    }
+}
+
 impl Camera {
     // v_fov is top to bottom in radians
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         look_from: Vec3,
         look_at: Vec3,
@@ -48,17 +93,16 @@ impl Camera {
             origin: look_from,
             u,
             v,
-            w,
             lens_radius,
             open_time,
             close_time,
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = random_in_unit_disk().scalar_mul(self.lens_radius);
+    pub fn get_ray(&self, s: f32, t: f32, sampler: &mut dyn Sampler) -> Ray {
+        let rd = random_in_unit_disk(sampler).scalar_mul(self.lens_radius);
         let offset = self.u.scalar_mul(rd.x()) + self.v.scalar_mul(rd.y());
-        let time = self.open_time + thread_rng().gen::<f32>() * (self.close_time - self.open_time);
+        let time = self.open_time + sampler.get_1d() * (self.close_time - self.open_time);
         Ray::new_at_time(
             self.origin + offset,
             self.lower_left_corner + self.horizontal.scalar_mul(s) + self.vertical.scalar_mul(t)
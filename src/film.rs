@@ -0,0 +1,158 @@
+use crate::{filter::Filter, tile::Tile, vec3::Vec3};
+use std::io::{Error, Write};
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Default)]
+struct FilmPixel {
+    weighted_sum: Vec3,
+    weight_sum: f32,
+}
+
+// Accumulates filtered samples in a linear float buffer. Image rows are stored top down and continuous
+// film coordinates put the center of pixel (x, y) at (x + 0.5, y + 0.5).
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Box<dyn Filter>,
+    pixels: Mutex<Vec<FilmPixel>>,
+    sample_counts: Mutex<Vec<usize>>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Box<dyn Filter>) -> Self {
+        Film {
+            width,
+            height,
+            filter,
+            pixels: Mutex::new(vec![FilmPixel::default(); width * height]),
+            sample_counts: Mutex::new(vec![0; width * height]),
+        }
+    }
+
+    // A tile's samples can land in pixels up to the filter radius outside it, so the film tile is padded
+    pub fn tile(&self, tile: &Tile) -> FilmTile<'_> {
+        let radius = self.filter.radius();
+        let x0 = (tile.x0 as f32 - radius).floor().max(0.0) as usize;
+        let y0 = (tile.y0 as f32 - radius).floor().max(0.0) as usize;
+        let x1 = ((tile.x1 as f32 + radius).ceil() as usize).min(self.width);
+        let y1 = ((tile.y1 as f32 + radius).ceil() as usize).min(self.height);
+        let bounds = Tile::new(x0, y0, x1, y1);
+        FilmTile {
+            filter: self.filter.as_ref(),
+            pixels: vec![FilmPixel::default(); bounds.width() * bounds.height()],
+            sample_counts: vec![0; tile.width() * tile.height()],
+            bounds,
+            tile: *tile,
+        }
+    }
+
+    pub fn merge_tile(&self, film_tile: FilmTile) {
+        let bounds = film_tile.bounds;
+        {
+            let mut pixels = self.pixels.lock().unwrap();
+            for (tile_pixel, (x, y)) in film_tile.pixels.iter().zip(bounds.pixels()) {
+                let pixel = &mut pixels[y * self.width + x];
+                pixel.weighted_sum += tile_pixel.weighted_sum;
+                pixel.weight_sum += tile_pixel.weight_sum;
+            }
+        }
+        let mut sample_counts = self.sample_counts.lock().unwrap();
+        for (count, (x, y)) in film_tile.sample_counts.iter().zip(film_tile.tile.pixels()) {
+            sample_counts[y * self.width + x] = *count;
+        }
+    }
+
+    // The reconstructed linear radiance of every pixel, which all outputs are produced from
+    pub fn linear_rgb(&self) -> Vec<Vec3> {
+        self.pixels
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|pixel| {
+                if pixel.weight_sum != 0.0 {
+                    pixel.weighted_sum.scalar_mul(1.0 / pixel.weight_sum)
+                } else {
+                    Vec3::new(0.0, 0.0, 0.0)
+                }
+            })
+            .collect()
+    }
+
+    pub fn write_ppm(&self, output: &mut dyn Write) -> Result<(), Error> {
+        write!(output, "P3\n{} {} \n255\n", self.width, self.height)?;
+        for color in self.linear_rgb() {
+            // Filters with negative lobes can ring below zero
+            let gamma = |c: f32| c.clamp(0.0, 1.0).sqrt();
+            let ir = (255.99 * gamma(color.r())) as i32;
+            let ig = (255.99 * gamma(color.g())) as i32;
+            let ib = (255.99 * gamma(color.b())) as i32;
+            writeln!(output, "{} {} {}", ir, ig, ib)?;
+        }
+        Ok(())
+    }
+
+    // Portable float map, keeping the linear values unclamped. PFM stores rows bottom up.
+    pub fn write_pfm(&self, output: &mut dyn Write) -> Result<(), Error> {
+        write!(output, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        let rgb = self.linear_rgb();
+        for row in rgb.chunks(self.width).rev() {
+            for color in row {
+                for component in color.data() {
+                    output.write_all(&component.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Debug image of how many samples each pixel took, scaled so the busiest pixel is white
+    pub fn write_sample_count_ppm(&self, output: &mut dyn Write) -> Result<(), Error> {
+        write!(output, "P3\n{} {} \n255\n", self.width, self.height)?;
+        let sample_counts = self.sample_counts.lock().unwrap();
+        let max_count = sample_counts.iter().copied().max().unwrap_or(0).max(1);
+        for &count in sample_counts.iter() {
+            let level = (255.99 * count as f32 / max_count as f32) as i32;
+            writeln!(output, "{} {} {}", level, level, level)?;
+        }
+        Ok(())
+    }
+}
+
+// Private accumulation buffer for one tile, merged back into the film once the tile is done
+pub struct FilmTile<'a> {
+    filter: &'a dyn Filter,
+    bounds: Tile,
+    tile: Tile,
+    pixels: Vec<FilmPixel>,
+    sample_counts: Vec<usize>,
+}
+
+impl<'a> FilmTile<'a> {
+    pub fn add_sample(&mut self, film_x: f32, film_y: f32, color: Vec3) {
+        // Guard against NaNs and infinities from degenerate paths poisoning the neighbouring pixels
+        if !color.data().iter().all(|c| c.is_finite()) {
+            return;
+        }
+        let radius = self.filter.radius();
+        let x0 = ((film_x - 0.5 - radius).ceil().max(self.bounds.x0 as f32)) as usize;
+        let y0 = ((film_y - 0.5 - radius).ceil().max(self.bounds.y0 as f32)) as usize;
+        let x1 = ((film_x - 0.5 + radius).floor() as isize + 1).min(self.bounds.x1 as isize);
+        let y1 = ((film_y - 0.5 + radius).floor() as isize + 1).min(self.bounds.y1 as isize);
+        for y in y0 as isize..y1 {
+            for x in x0 as isize..x1 {
+                let weight = self.filter.evaluate(x as f32 + 0.5 - film_x, y as f32 + 0.5 - film_y);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = (y as usize - self.bounds.y0) * self.bounds.width() + (x as usize - self.bounds.x0);
+                let pixel = &mut self.pixels[index];
+                pixel.weighted_sum += color.scalar_mul(weight);
+                pixel.weight_sum += weight;
+            }
+        }
+    }
+
+    pub fn set_sample_count(&mut self, x: usize, y: usize, count: usize) {
+        self.sample_counts[(y - self.tile.y0) * self.tile.width() + (x - self.tile.x0)] = count;
+    }
+}
@@ -0,0 +1,198 @@
+use std::f32::consts::PI;
+
+// Pixel reconstruction filter. Samples contribute to every pixel whose center lies within radius of
+// them, weighted by evaluate at the offset from that center.
+pub trait Filter: Sync {
+    fn radius(&self) -> f32;
+
+    fn evaluate(&self, x: f32, y: f32) -> f32;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn create(self) -> Box<dyn Filter> {
+        match self {
+            FilterKind::Box => Box::new(BoxFilter::new(0.5)),
+            FilterKind::Tent => Box::new(TentFilter::new(1.0)),
+            FilterKind::Gaussian => Box::new(GaussianFilter::new(1.5, 0.5)),
+            FilterKind::Mitchell => Box::new(MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)),
+            FilterKind::Lanczos => Box::new(LanczosFilter::new(3.0, 3.0)),
+        }
+    }
+}
+
+impl std::str::FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            "lanczos" => Ok(FilterKind::Lanczos),
+            _ => Err(format!("Unknown filter '{}'", s)),
+        }
+    }
+}
+
+pub struct BoxFilter {
+    radius: f32,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f32) -> Self {
+        BoxFilter { radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        if x.abs() <= self.radius && y.abs() <= self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+pub struct TentFilter {
+    radius: f32,
+}
+
+impl TentFilter {
+    pub fn new(radius: f32) -> Self {
+        TentFilter { radius }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0)
+    }
+}
+
+pub struct GaussianFilter {
+    radius: f32,
+    sigma: f32,
+    // Value at the radius, subtracted so the filter falls to zero at its edge
+    edge: f32,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f32, sigma: f32) -> Self {
+        GaussianFilter {
+            radius,
+            sigma,
+            edge: gaussian(radius, sigma),
+        }
+    }
+}
+
+fn gaussian(x: f32, sigma: f32) -> f32 {
+    (-x * x / (2.0 * sigma * sigma)).exp() / (2.0 * PI * sigma * sigma).sqrt()
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        (gaussian(x, self.sigma) - self.edge).max(0.0) * (gaussian(y, self.sigma) - self.edge).max(0.0)
+    }
+}
+
+// Mitchell-Netravali cubic, B = C = 1/3 being the parameters recommended in the paper
+pub struct MitchellFilter {
+    radius: f32,
+    b: f32,
+    c: f32,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f32, b: f32, c: f32) -> Self {
+        MitchellFilter { radius, b, c }
+    }
+
+    // The cubic is defined over [-2, 2] so x is scaled to that range
+    fn mitchell_1d(&self, x: f32) -> f32 {
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        if x > 2.0 {
+            0.0
+        } else if x > 1.0 {
+            ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c))
+                / 6.0
+        } else {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b))
+                / 6.0
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.mitchell_1d(x) * self.mitchell_1d(y)
+    }
+}
+
+// Windowed sinc, tau being the number of sinc lobes kept inside the window
+pub struct LanczosFilter {
+    radius: f32,
+    tau: f32,
+}
+
+impl LanczosFilter {
+    pub fn new(radius: f32, tau: f32) -> Self {
+        LanczosFilter { radius, tau }
+    }
+
+    fn lanczos_1d(&self, x: f32) -> f32 {
+        let x = x.abs() / self.radius;
+        if x > 1.0 {
+            0.0
+        } else {
+            sinc(x * self.tau) * sinc(x)
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f32, y: f32) -> f32 {
+        self.lanczos_1d(x) * self.lanczos_1d(y)
+    }
+}
@@ -0,0 +1,272 @@
+// Angular intensity distribution of a light fixture from an IESNA LM-63 photometric data file. Only
+// type C photometry is supported, which is what nearly all architectural fixtures use: vertical angles
+// are measured from straight down and horizontal angles around the vertical axis.
+use crate::vec3::Vec3;
+
+pub struct IesProfile {
+    // Degrees, both increasing
+    vertical_angles: Vec<f32>,
+    horizontal_angles: Vec<f32>,
+    // Intensity for each horizontal angle in turn, over all the vertical angles, scaled to peak at one
+    candela: Vec<f32>,
+}
+
+impl IesProfile {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut lines = source.lines();
+        // Keyword lines come before TILT, which says whether the intensity depends on the lamp's tilt
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim_start().starts_with("TILT=") => break line.trim()["TILT=".len()..].to_string(),
+                Some(_) => {}
+                None => return Err("Missing TILT line".to_string()),
+            }
+        };
+        let rest: Vec<&str> = lines.collect();
+        let mut numbers = rest
+            .iter()
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f32>().map_err(|_| format!("Invalid number '{}'", token)));
+        let mut next = || numbers.next().unwrap_or_else(|| Err("Unexpected end of file".to_string()));
+
+        match tilt.as_str() {
+            "NONE" => {}
+            // The tilt table only matters for lamps mounted at an angle, skip past it
+            "INCLUDE" => {
+                next()?;
+                let count = next()? as usize;
+                for _ in 0..2 * count {
+                    next()?;
+                }
+            }
+            _ => return Err(format!("Tilt data in a separate file ({}) is not supported", tilt)),
+        }
+
+        let _lamp_count = next()?;
+        let _lumens_per_lamp = next()?;
+        let _candela_multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()?;
+        let _units = next()?;
+        let _width = next()?;
+        let _length = next()?;
+        let _height = next()?;
+        let _ballast_factor = next()?;
+        let _future_use = next()?;
+        let _input_watts = next()?;
+        if photometric_type != 1.0 {
+            return Err(format!("Only type C photometry is supported, not type {}", photometric_type));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err("Profile has no angles".to_string());
+        }
+        let candela_count = vertical_count
+            .checked_mul(horizontal_count)
+            .ok_or_else(|| format!("Too many angles ({} by {})", vertical_count, horizontal_count))?;
+
+        let mut read = |count: usize| (0..count).map(|_| next()).collect::<Result<Vec<f32>, String>>();
+        let vertical_angles = read(vertical_count)?;
+        let horizontal_angles = read(horizontal_count)?;
+        let mut candela = read(candela_count)?;
+        let increasing = |angles: &[f32]| angles.windows(2).all(|pair| pair[0] < pair[1]);
+        if !increasing(&vertical_angles) || !increasing(&horizontal_angles) {
+            return Err("Angles must be increasing".to_string());
+        }
+
+        // The absolute scale is set by the light using the profile
+        let peak = candela.iter().cloned().fold(0.0, f32::max);
+        if peak <= 0.0 {
+            return Err("Profile emits no light".to_string());
+        }
+        for value in &mut candela {
+            *value = value.max(0.0) / peak;
+        }
+        Ok(IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        })
+    }
+
+    // Relative intensity towards the unit direction w, in the fixture's frame where z points straight
+    // down, x along horizontal angle zero and y along horizontal angle 90
+    pub fn evaluate(&self, w: &Vec3) -> f32 {
+        let vertical = w.z().clamp(-1.0, 1.0).acos().to_degrees();
+        let horizontal = self.fold_horizontal(w.y().atan2(w.x()).to_degrees().rem_euclid(360.0));
+
+        let (v0, v1, tv) = match bracket(&self.vertical_angles, vertical) {
+            Some(bracket) => bracket,
+            // Nothing was measured outside the vertical range
+            None => return 0.0,
+        };
+        let (h0, h1, th) = bracket(&self.horizontal_angles, horizontal).unwrap_or((0, 0, 0.0));
+        let value = |h: usize, v: usize| self.candela[h * self.vertical_angles.len() + v];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        lerp(
+            lerp(value(h0, v0), value(h0, v1), tv),
+            lerp(value(h1, v0), value(h1, v1), tv),
+            th,
+        )
+    }
+
+    // Maps a horizontal angle in [0, 360) into the range the file covers, using the symmetry implied by
+    // its first and last horizontal angles
+    fn fold_horizontal(&self, angle: f32) -> f32 {
+        let first = self.horizontal_angles[0];
+        let last = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        if self.horizontal_angles.len() == 1 {
+            // Rotationally symmetric
+            first
+        } else if first == 0.0 && last == 90.0 {
+            // Symmetric in each quadrant
+            let angle = if angle > 180.0 { 360.0 - angle } else { angle };
+            if angle > 90.0 {
+                180.0 - angle
+            } else {
+                angle
+            }
+        } else if first == 0.0 && last == 180.0 {
+            // Symmetric about the 0-180 degree plane
+            if angle > 180.0 {
+                360.0 - angle
+            } else {
+                angle
+            }
+        } else if first == 90.0 && last == 270.0 {
+            // Symmetric about the 90-270 degree plane
+            if (90.0..=270.0).contains(&angle) {
+                angle
+            } else {
+                (180.0 - angle).rem_euclid(360.0)
+            }
+        } else {
+            angle
+        }
+    }
+}
+
+// Indices of the angles either side of angle and how far it is between them, or None outside their range
+fn bracket(angles: &[f32], angle: f32) -> Option<(usize, usize, f32)> {
+    let last = angles.len() - 1;
+    if last == 0 {
+        return Some((0, 0, 0.0));
+    }
+    if angle < angles[0] || angle > angles[last] {
+        return None;
+    }
+    let upper = angles.partition_point(|&a| a <= angle).clamp(1, last);
+    let lower = upper - 1;
+    Some((lower, upper, (angle - angles[lower]) / (angles[upper] - angles[lower])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LM-63 file with the given tilt section, angles and candela values, one horizontal angle at a time
+    fn source(tilt: &str, vertical: &[f32], horizontal: &[f32], candela: &[f32]) -> String {
+        let list = |values: &[f32]| values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(" ");
+        format!(
+            "IESNA:LM-63-2002\n[TEST] test\n{}\n1 1000 1 {} {} 1 2 0 0 0\n1 1 100\n{}\n{}\n{}\n",
+            tilt,
+            vertical.len(),
+            horizontal.len(),
+            list(vertical),
+            list(horizontal),
+            list(candela),
+        )
+    }
+
+    fn direction(vertical: f32, horizontal: f32) -> Vec3 {
+        let (vertical, horizontal) = (vertical.to_radians(), horizontal.to_radians());
+        Vec3::new(vertical.sin() * horizontal.cos(), vertical.sin() * horizontal.sin(), vertical.cos())
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "expected {} but found {}", expected, actual);
+    }
+
+    #[test]
+    fn parses_without_tilt() {
+        let profile = IesProfile::parse(&source("TILT=NONE", &[0.0, 90.0], &[0.0], &[200.0, 100.0])).unwrap();
+        assert_close(profile.evaluate(&direction(0.0, 0.0)), 1.0);
+        assert_close(profile.evaluate(&direction(45.0, 123.0)), 0.75);
+        assert_close(profile.evaluate(&direction(90.0, 0.0)), 0.5);
+        // Nothing was measured above the horizon
+        assert_close(profile.evaluate(&direction(135.0, 0.0)), 0.0);
+    }
+
+    #[test]
+    fn skips_included_tilt_table() {
+        let tilt = "TILT=INCLUDE\n1\n3\n0 45 90\n1.0 0.9 0.8";
+        let profile = IesProfile::parse(&source(tilt, &[0.0, 90.0], &[0.0], &[200.0, 100.0])).unwrap();
+        assert_eq!(profile.vertical_angles, vec![0.0, 90.0]);
+        assert_eq!(profile.horizontal_angles, vec![0.0]);
+        assert_close(profile.evaluate(&direction(90.0, 0.0)), 0.5);
+    }
+
+    #[test]
+    fn rejects_tilt_file() {
+        assert!(IesProfile::parse(&source("TILT=lamp.tlt", &[0.0], &[0.0], &[1.0])).is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_angle_counts() {
+        let source = "IESNA:LM-63-2002\nTILT=NONE\n1 1000 1 1e30 1e30 1 2 0 0 0\n1 1 100\n0\n0\n1\n";
+        let error = IesProfile::parse(source).err().unwrap();
+        assert!(error.starts_with("Too many angles"), "{}", error);
+    }
+
+    fn folded(horizontal: &[f32], angle: f32) -> f32 {
+        let candela = vec![1.0; horizontal.len()];
+        IesProfile::parse(&source("TILT=NONE", &[0.0], horizontal, &candela)).unwrap().fold_horizontal(angle)
+    }
+
+    #[test]
+    fn folds_rotationally_symmetric() {
+        assert_eq!(folded(&[0.0], 250.0), 0.0);
+    }
+
+    #[test]
+    fn folds_quadrant_symmetric() {
+        let horizontal = [0.0, 45.0, 90.0];
+        assert_close(folded(&horizontal, 30.0), 30.0);
+        assert_close(folded(&horizontal, 120.0), 60.0);
+        assert_close(folded(&horizontal, 200.0), 20.0);
+        assert_close(folded(&horizontal, 300.0), 60.0);
+    }
+
+    #[test]
+    fn folds_symmetric_about_0_180_plane() {
+        let horizontal = [0.0, 90.0, 180.0];
+        assert_close(folded(&horizontal, 120.0), 120.0);
+        assert_close(folded(&horizontal, 300.0), 60.0);
+    }
+
+    #[test]
+    fn folds_symmetric_about_90_270_plane() {
+        let horizontal = [90.0, 180.0, 270.0];
+        assert_close(folded(&horizontal, 200.0), 200.0);
+        assert_close(folded(&horizontal, 30.0), 150.0);
+        assert_close(folded(&horizontal, 330.0), 210.0);
+    }
+
+    #[test]
+    fn keeps_full_circle() {
+        let horizontal = [0.0, 90.0, 180.0, 270.0, 360.0];
+        assert_close(folded(&horizontal, 300.0), 300.0);
+    }
+
+    #[test]
+    fn horizontal_angles_run_from_x_towards_y() {
+        let candela = [100.0, 100.0, 50.0, 50.0, 25.0, 25.0, 10.0, 10.0, 100.0, 100.0];
+        let profile = IesProfile::parse(&source("TILT=NONE", &[0.0, 90.0], &[0.0, 90.0, 180.0, 270.0, 360.0], &candela))
+            .unwrap();
+        assert_close(profile.evaluate(&direction(90.0, 0.0)), 1.0);
+        assert_close(profile.evaluate(&direction(90.0, 90.0)), 0.5);
+        assert_close(profile.evaluate(&direction(90.0, 180.0)), 0.25);
+        assert_close(profile.evaluate(&direction(90.0, 270.0)), 0.1);
+    }
+}
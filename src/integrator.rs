@@ -0,0 +1,461 @@
+use crate::{
+    material::BsdfSample,
+    medium::MediumSample,
+    object::HitRecord,
+    ray::Ray,
+    sampler::Sampler,
+    scene::Scene,
+    spectrum::PathColor,
+    vec3::Vec3,
+};
+
+// Estimates the radiance arriving at the camera along a ray, in whatever the path's color carries
+pub trait Integrator: Sync {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler, color: &mut PathColor) -> Vec3;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum IntegratorKind {
+    BsdfSampling,
+    DirectLighting,
+    Mis(Heuristic),
+}
+
+impl IntegratorKind {
+    pub fn create(self, path_depth: PathDepth) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::BsdfSampling => Box::new(BsdfSamplingIntegrator { path_depth }),
+            IntegratorKind::DirectLighting => Box::new(DirectLightingIntegrator { path_depth }),
+            IntegratorKind::Mis(heuristic) => Box::new(MisIntegrator::new(heuristic, path_depth)),
+        }
+    }
+}
+
+impl std::str::FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            // recursive is its name from before paths were traced iteratively
+            "bsdf" | "recursive" => Ok(IntegratorKind::BsdfSampling),
+            "direct" => Ok(IntegratorKind::DirectLighting),
+            "mis" => Ok(IntegratorKind::Mis(Heuristic::Power)),
+            "mis-balance" => Ok(IntegratorKind::Mis(Heuristic::Balance)),
+            _ => Err(format!("Unknown integrator '{}'", s)),
+        }
+    }
+}
+
+// Limits on how many times a path may scatter, in total and for each kind of bounce. Past
+// russian_roulette_depth bounces paths are also terminated at random in proportion to how little they
+// could still contribute.
+#[derive(Clone, Copy, Debug)]
+pub struct PathDepth {
+    pub max_depth: u32,
+    pub max_diffuse: u32,
+    pub max_specular: u32,
+    pub max_transmission: u32,
+    pub max_volume: u32,
+    pub russian_roulette_depth: u32,
+}
+
+impl Default for PathDepth {
+    fn default() -> Self {
+        PathDepth {
+            max_depth: 50,
+            max_diffuse: 8,
+            max_specular: 32,
+            max_transmission: 32,
+            max_volume: 64,
+            russian_roulette_depth: 3,
+        }
+    }
+}
+
+// Bounce counts along a single path, checked against the PathDepth limits
+struct PathTracker<'a> {
+    limits: &'a PathDepth,
+    depth: u32,
+    diffuse: u32,
+    specular: u32,
+    transmission: u32,
+    volume: u32,
+}
+
+impl<'a> PathTracker<'a> {
+    fn new(limits: &'a PathDepth) -> Self {
+        PathTracker {
+            limits,
+            depth: 0,
+            diffuse: 0,
+            specular: 0,
+            transmission: 0,
+            volume: 0,
+        }
+    }
+
+    // Records a surface bounce, returning false if the path has now gone past one of its limits
+    fn scatter(&mut self, hit_record: &HitRecord, sample: &BsdfSample) -> bool {
+        self.depth += 1;
+        if (sample.wi.dot(&hit_record.geometric_normal) > 0.0) != hit_record.front_face {
+            self.transmission += 1;
+        } else if sample.is_specular {
+            self.specular += 1;
+        } else {
+            self.diffuse += 1;
+        }
+        self.depth <= self.limits.max_depth
+            && self.diffuse <= self.limits.max_diffuse
+            && self.specular <= self.limits.max_specular
+            && self.transmission <= self.limits.max_transmission
+    }
+
+    // Records a scattering event inside a participating medium
+    fn volume_scatter(&mut self) -> bool {
+        self.depth += 1;
+        self.volume += 1;
+        self.depth <= self.limits.max_depth && self.volume <= self.limits.max_volume
+    }
+
+    // Randomly ends paths whose throughput has become small, boosting the survivors to compensate
+    fn russian_roulette(&self, throughput: &mut Vec3, sampler: &mut dyn Sampler) -> bool {
+        if self.depth < self.limits.russian_roulette_depth {
+            return true;
+        }
+        let u = sampler.get_1d();
+        let survival = throughput.max_component().min(0.95);
+        if u >= survival {
+            return false;
+        }
+        *throughput = throughput.scalar_mul(1.0 / survival);
+        true
+    }
+}
+
+// Extends a path by sampling the BSDF at a hit, updating its throughput. Returns the sample if the path
+// should carry on along it.
+fn continue_path(
+    hit_record: &HitRecord,
+    wo: &Vec3,
+    tracker: &mut PathTracker,
+    throughput: &mut Vec3,
+    sampler: &mut dyn Sampler,
+) -> Option<BsdfSample> {
+    let sample = match hit_record.material.sample(hit_record, wo, sampler) {
+        Some(sample) if sample.pdf > 0.0 => sample,
+        _ => return None,
+    };
+    if !tracker.scatter(hit_record, &sample) {
+        return None;
+    }
+    *throughput = *throughput * sample.f.scalar_mul(1.0 / sample.pdf);
+    if !tracker.russian_roulette(throughput, sampler) {
+        return None;
+    }
+    Some(sample)
+}
+
+// Where a ray travelling through the medium inside the material it hits, if any, first interacts
+enum MediumInteraction {
+    Surface,
+    // Scattered on the way, so the path carries on from inside the medium along the ray
+    Scattered(Ray),
+    Terminated,
+}
+
+// Random walk step through the medium the ray travelled through to reach the hit, updating the path's
+// throughput
+fn sample_medium(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    tracker: &mut PathTracker,
+    throughput: &mut Vec3,
+    color: &PathColor,
+    sampler: &mut dyn Sampler,
+) -> MediumInteraction {
+    let medium = match hit_record.material.medium() {
+        Some(medium) if !hit_record.front_face => medium,
+        _ => return MediumInteraction::Surface,
+    };
+    let direction = ray.direction().normalize();
+    match medium.sample(hit_record.t * ray.direction().length(), color, sampler) {
+        MediumSample::Passed { weight } => {
+            *throughput = *throughput * weight;
+            MediumInteraction::Surface
+        }
+        MediumSample::Scattered { distance, weight } => {
+            *throughput = *throughput * weight;
+            if !tracker.volume_scatter() || !tracker.russian_roulette(throughput, sampler) {
+                return MediumInteraction::Terminated;
+            }
+            let point = ray.origin() + direction.scalar_mul(distance);
+            let wi = medium.sample_direction(sampler.get_2d());
+            MediumInteraction::Scattered(Ray::new_at_time(point, wi, ray.time()))
+        }
+    }
+}
+
+// Ray and BSDF density to carry on with after scattering inside a medium, for integrators that sample
+// lights. Lights can't be sampled from inside the medium, whose surface blocks them, so whatever the ray
+// hits is counted in full, as after a delta lobe.
+fn scattered_unlit(scattered: Ray) -> (Ray, Option<f32>) {
+    (scattered, None)
+}
+
+// Follows BSDF samples until they happen to escape to the background or hit an emitter
+pub struct BsdfSamplingIntegrator {
+    path_depth: PathDepth,
+}
+
+impl Integrator for BsdfSamplingIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler, color: &mut PathColor) -> Vec3 {
+        let mut radiance = Vec3::new(0.0, 0.0, 0.0);
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = ray.clone();
+        let mut tracker = PathTracker::new(&self.path_depth);
+
+        loop {
+            let mut hit_record = match scene.world.hit(&ray, 0.0, f32::MAX) {
+                Some(hit_record) => hit_record,
+                None => {
+                    radiance += throughput * color.upsample(scene.background(&ray));
+                    break;
+                }
+            };
+            let wo = -ray.direction().normalize();
+            set_color(&mut hit_record, color);
+            match sample_medium(&ray, &hit_record, &mut tracker, &mut throughput, color, sampler) {
+                MediumInteraction::Surface => {}
+                MediumInteraction::Scattered(scattered) => {
+                    ray = scattered;
+                    continue;
+                }
+                MediumInteraction::Terminated => break,
+            }
+            throughput = throughput * color.upsample_albedo(medium_transmittance(&ray, &hit_record));
+            radiance += throughput * color.upsample(hit_record.material.emitted(&hit_record, &wo));
+
+            match continue_path(&hit_record, &wo, &mut tracker, &mut throughput, sampler) {
+                Some(sample) => ray = hit_record.spawn_ray(&sample.wi, ray.time()),
+                None => break,
+            }
+        }
+        radiance
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Heuristic {
+    Balance,
+    Power,
+}
+
+impl Heuristic {
+    // Weight of a sample drawn with density pdf_f when the other strategy would have drawn it with pdf_g
+    #[inline]
+    fn weight(self, pdf_f: f32, pdf_g: f32) -> f32 {
+        let (f, g) = match self {
+            Heuristic::Balance => (pdf_f, pdf_g),
+            Heuristic::Power => (pdf_f * pdf_f, pdf_g * pdf_g),
+        };
+        if f == 0.0 {
+            0.0
+        } else {
+            f / (f + g)
+        }
+    }
+}
+
+// Tells the material at a hit which wavelengths it is seen at. A dispersive material sends each wavelength
+// its own way, so only the hero wavelength can carry on through it.
+fn set_color(hit_record: &mut HitRecord, color: &mut PathColor) {
+    if hit_record.material.is_dispersive() {
+        color.terminate_secondary();
+    }
+    hit_record.color = *color;
+}
+
+// Attenuation along the ray up to the hit, from whatever the material absorbs if the ray travelled
+// through its inside
+fn medium_transmittance(ray: &Ray, hit_record: &HitRecord) -> Vec3 {
+    let distance = hit_record.t * ray.direction().length();
+    hit_record.material.transmittance(hit_record, distance)
+}
+
+// Direct lighting at a surface from one light picked by the scene's light BVH. With a heuristic the light sample is
+// weighted against the chance of the BSDF having sampled the same direction.
+fn sample_light(
+    hit_record: &HitRecord,
+    wo: &Vec3,
+    time: f32,
+    scene: &Scene,
+    heuristic: Option<Heuristic>,
+    color: &PathColor,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let light_choice = sampler.get_1d();
+    let u = sampler.get_2d();
+    let (light, light_pmf) = match scene.sample_light(&hit_record.point, &hit_record.normal, light_choice) {
+        Some(choice) => choice,
+        None => return Vec3::new(0.0, 0.0, 0.0),
+    };
+    let sample = match light.sample_li(&hit_record.point, u) {
+        Some(sample) if sample.pdf > 0.0 => sample,
+        _ => return Vec3::new(0.0, 0.0, 0.0),
+    };
+    let f = hit_record.material.eval(hit_record, wo, &sample.wi);
+    if f.squared_length() == 0.0 || !scene.unoccluded(hit_record, &sample.wi, sample.distance, time) {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+    let light_pdf = light_pmf * sample.pdf;
+    let weight = match heuristic {
+        Some(heuristic) if !light.is_delta() => {
+            heuristic.weight(light_pdf, hit_record.material.pdf(hit_record, wo, &sample.wi))
+        }
+        _ => 1.0,
+    };
+    (f * color.upsample(sample.radiance)).scalar_mul(weight / light_pdf)
+}
+
+// Path tracer with next event estimation: at every bounce off a non specular material one of the
+// scene's lights is sampled and connected to with a shadow ray
+pub struct DirectLightingIntegrator {
+    path_depth: PathDepth,
+}
+
+impl Integrator for DirectLightingIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler, color: &mut PathColor) -> Vec3 {
+        let mut radiance = Vec3::new(0.0, 0.0, 0.0);
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = ray.clone();
+        // Density of the BSDF sample that produced the current ray, None when the previous vertex couldn't be
+        // lit directly. Otherwise lights the ray finds were already counted by sampling them there.
+        let mut bsdf_pdf: Option<f32> = None;
+        let mut tracker = PathTracker::new(&self.path_depth);
+
+        loop {
+            let mut hit_record = match scene.world.hit(&ray, 0.0, f32::MAX) {
+                Some(hit_record) => hit_record,
+                None => {
+                    if bsdf_pdf.is_none() || scene.environment_light().is_none() {
+                        radiance += throughput * color.upsample(scene.background(&ray));
+                    }
+                    break;
+                }
+            };
+            let wo = -ray.direction().normalize();
+            set_color(&mut hit_record, color);
+            match sample_medium(&ray, &hit_record, &mut tracker, &mut throughput, color, sampler) {
+                MediumInteraction::Surface => {}
+                MediumInteraction::Scattered(scattered) => {
+                    (ray, bsdf_pdf) = scattered_unlit(scattered);
+                    continue;
+                }
+                MediumInteraction::Terminated => break,
+            }
+            throughput = throughput * color.upsample_albedo(medium_transmittance(&ray, &hit_record));
+            if bsdf_pdf.is_none() || hit_record.light.is_none() {
+                radiance += throughput * color.upsample(hit_record.material.emitted(&hit_record, &wo));
+            }
+
+            if !hit_record.material.is_specular() {
+                radiance += throughput * sample_light(&hit_record, &wo, ray.time(), scene, None, color, sampler);
+            }
+
+            match continue_path(&hit_record, &wo, &mut tracker, &mut throughput, sampler) {
+                Some(sample) => {
+                    ray = hit_record.spawn_ray(&sample.wi, ray.time());
+                    bsdf_pdf = if sample.is_specular { None } else { Some(sample.pdf) };
+                }
+                None => break,
+            }
+        }
+        radiance
+    }
+}
+
+// Path tracer that lights every non specular vertex both by sampling a light and by following the BSDF
+// sample, combining the two with multiple importance sampling
+pub struct MisIntegrator {
+    heuristic: Heuristic,
+    path_depth: PathDepth,
+}
+
+impl MisIntegrator {
+    pub fn new(heuristic: Heuristic, path_depth: PathDepth) -> Self {
+        MisIntegrator { heuristic, path_depth }
+    }
+}
+
+impl Integrator for MisIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler, color: &mut PathColor) -> Vec3 {
+        let mut radiance = Vec3::new(0.0, 0.0, 0.0);
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = ray.clone();
+        // Density of the BSDF sample that produced the current ray, None if it came from a delta lobe or
+        // the camera so that whatever it hits is counted in full
+        let mut bsdf_pdf: Option<f32> = None;
+        let mut previous_point = ray.origin();
+        let mut previous_normal = Vec3::new(0.0, 0.0, 0.0);
+        let mut tracker = PathTracker::new(&self.path_depth);
+
+        loop {
+            let direction = ray.direction().normalize();
+            let mut hit_record = match scene.world.hit(&ray, 0.0, f32::MAX) {
+                Some(hit_record) => hit_record,
+                None => {
+                    let weight = match (bsdf_pdf, scene.environment_light()) {
+                        (Some(bsdf_pdf), Some(light)) => {
+                            let light_pmf = scene.light_pmf(&previous_point, &previous_normal, light);
+                            let light_pdf = light_pmf * light.pdf_li(&previous_point, &direction, None);
+                            self.heuristic.weight(bsdf_pdf, light_pdf)
+                        }
+                        _ => 1.0,
+                    };
+                    radiance += (throughput * color.upsample(scene.background(&ray))).scalar_mul(weight);
+                    break;
+                }
+            };
+            let wo = -direction;
+            set_color(&mut hit_record, color);
+            match sample_medium(&ray, &hit_record, &mut tracker, &mut throughput, color, sampler) {
+                MediumInteraction::Surface => {}
+                MediumInteraction::Scattered(scattered) => {
+                    (ray, bsdf_pdf) = scattered_unlit(scattered);
+                    continue;
+                }
+                MediumInteraction::Terminated => break,
+            }
+            throughput = throughput * color.upsample_albedo(medium_transmittance(&ray, &hit_record));
+
+            let emitted = color.upsample(hit_record.material.emitted(&hit_record, &wo));
+            if emitted.squared_length() > 0.0 {
+                let weight = match (bsdf_pdf, hit_record.light) {
+                    (Some(bsdf_pdf), Some(light)) => {
+                        let light_pmf = scene.light_pmf(&previous_point, &previous_normal, light);
+                        let light_pdf = light_pmf * light.pdf_li(&previous_point, &direction, Some(&hit_record));
+                        self.heuristic.weight(bsdf_pdf, light_pdf)
+                    }
+                    _ => 1.0,
+                };
+                radiance += (throughput * emitted).scalar_mul(weight);
+            }
+
+            if !hit_record.material.is_specular() {
+                let heuristic = Some(self.heuristic);
+                radiance += throughput * sample_light(&hit_record, &wo, ray.time(), scene, heuristic, color, sampler);
+            }
+
+            match continue_path(&hit_record, &wo, &mut tracker, &mut throughput, sampler) {
+                Some(sample) => {
+                    bsdf_pdf = if sample.is_specular { None } else { Some(sample.pdf) };
+                    previous_point = hit_record.point;
+                    previous_normal = hit_record.normal;
+                    ray = hit_record.spawn_ray(&sample.wi, ray.time());
+                }
+                None => break,
+            }
+        }
+        radiance
+    }
+}
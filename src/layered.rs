@@ -0,0 +1,275 @@
+// Materials built out of other materials
+use crate::{
+    material::{BsdfSample, Material},
+    medium::Medium,
+    microfacet::{fresnel_dielectric, reflect_about, TrowbridgeReitz},
+    object::HitRecord,
+    onb::Onb,
+    sampler::Sampler,
+    texture::Texture,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Blend of two materials, with weight giving the share of the second
+pub struct MixMaterial {
+    first: Box<dyn Material>,
+    second: Box<dyn Material>,
+    weight: Arc<dyn Texture>,
+}
+
+impl MixMaterial {
+    pub fn new(first: Box<dyn Material>, second: Box<dyn Material>, weight: Arc<dyn Texture>) -> Self {
+        MixMaterial { first, second, weight }
+    }
+
+    fn weight(&self, hit_record: &HitRecord) -> f32 {
+        self.weight.scalar(hit_record).clamp(0.0, 1.0)
+    }
+}
+
+fn mix(first: Vec3, second: Vec3, weight: f32) -> Vec3 {
+    first.lerp(&second, weight)
+}
+
+impl Material for MixMaterial {
+    fn eval(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let weight = self.weight(hit_record);
+        mix(self.first.eval(hit_record, wo, wi), self.second.eval(hit_record, wo, wi), weight)
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let weight = self.weight(hit_record);
+        let (chosen, share) = if sampler.get_1d() < weight {
+            (&self.second, weight)
+        } else {
+            (&self.first, 1.0 - weight)
+        };
+        let sample = chosen.sample(hit_record, wo, sampler)?;
+        if sample.is_specular {
+            // Delta lobes only come from the chosen material
+            return Some(BsdfSample {
+                f: sample.f.scalar_mul(share),
+                pdf: sample.pdf * share,
+                ..sample
+            });
+        }
+        let pdf = self.pdf(hit_record, wo, &sample.wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            f: self.eval(hit_record, wo, &sample.wi),
+            pdf,
+            ..sample
+        })
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> f32 {
+        let weight = self.weight(hit_record);
+        (1.0 - weight) * self.first.pdf(hit_record, wo, wi) + weight * self.second.pdf(hit_record, wo, wi)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.first.is_specular() && self.second.is_specular()
+    }
+
+    fn emitted(&self, hit_record: &HitRecord, wo: &Vec3) -> Vec3 {
+        let weight = self.weight(hit_record);
+        mix(self.first.emitted(hit_record, wo), self.second.emitted(hit_record, wo), weight)
+    }
+
+    fn transmittance(&self, hit_record: &HitRecord, distance: f32) -> Vec3 {
+        let weight = self.weight(hit_record);
+        mix(
+            self.first.transmittance(hit_record, distance),
+            self.second.transmittance(hit_record, distance),
+            weight,
+        )
+    }
+
+    // Only one side may be filled with a medium, as paths can't tell which side they entered through
+    fn medium(&self) -> Option<&Medium> {
+        self.first.medium().or_else(|| self.second.medium())
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.first.is_dispersive() || self.second.is_dispersive()
+    }
+}
+
+// Clear dielectric layer over a base material, like lacquer or varnish. Light either reflects off the coat
+// or passes through it twice, losing what the coat reflects on the way in and out plus what it absorbs
+// along the refracted paths. Light reflected back down inside the coat is dropped, so energy is never
+// gained. The base is seen through the coat without being refracted.
+pub struct Coated {
+    base: Box<dyn Material>,
+    refractive_index: f32,
+    distribution: TrowbridgeReitz,
+    // Optical depth straight through the coat, for each of red, green and blue
+    absorption: Vec3,
+}
+
+impl Coated {
+    pub fn new(base: Box<dyn Material>, refractive_index: f32) -> Self {
+        Coated {
+            base,
+            refractive_index,
+            distribution: TrowbridgeReitz::new(0.0, 0.0),
+            absorption: Vec3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // GGX alpha of the coat along each tangent of the shading frame
+    pub fn with_roughness(mut self, roughness_u: f32, roughness_v: f32) -> Self {
+        self.distribution = TrowbridgeReitz::new(roughness_u, roughness_v);
+        self
+    }
+
+    pub fn with_absorption(mut self, absorption: Vec3) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    // The coat is two sided, like the opaque materials it covers
+    fn frame(hit_record: &HitRecord, wo: &Vec3) -> Onb {
+        hit_record.shading_frame().facing(wo)
+    }
+
+    // Chance of sampling the coat rather than the base, kept from getting so small that highlights are
+    // mostly found by chance
+    fn coat_probability(&self, cos_theta_o: f32) -> f32 {
+        fresnel_dielectric(cos_theta_o, self.refractive_index).max(0.25)
+    }
+
+    fn coat_eval(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if self.distribution.effectively_smooth() || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wh = (*wo + *wi).normalize();
+        let fresnel = fresnel_dielectric(wo.dot(&wh), self.refractive_index);
+        self.distribution.d(&wh) * self.distribution.g(wo, wi) * fresnel / (4.0 * wo.z())
+    }
+
+    fn coat_pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if self.distribution.effectively_smooth() || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wh = (*wo + *wi).normalize();
+        self.distribution.visible_d(wo, &wh) / (4.0 * wo.dot(&wh).abs())
+    }
+
+    // What reaches the base through the coat and makes it back out, for local directions
+    fn base_scale(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let eta = self.refractive_index;
+        let (cos_o, cos_i) = (wo.z().abs(), wi.z().abs());
+        let transmitted = (1.0 - fresnel_dielectric(cos_o, eta)) * (1.0 - fresnel_dielectric(cos_i, eta));
+        let refracted_cos = |cos: f32| (1.0 - (1.0 - cos * cos) / (eta * eta)).max(1e-4).sqrt();
+        let depth = 1.0 / refracted_cos(cos_o) + 1.0 / refracted_cos(cos_i);
+        let absorbed = self.absorption.scalar_mul(-depth);
+        let transmittance = Vec3::new(absorbed.x().exp(), absorbed.y().exp(), absorbed.z().exp());
+        hit_record.color.upsample_albedo(transmittance).scalar_mul(transmitted)
+    }
+}
+
+impl Material for Coated {
+    fn eval(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let frame = Self::frame(hit_record, wo);
+        let (wo_local, wi_local) = (frame.to_local(wo), frame.to_local(wi));
+        if wo_local.z() <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let coat = self.coat_eval(&wo_local, &wi_local);
+        let base = self.base.eval(hit_record, wo, wi) * self.base_scale(hit_record, &wo_local, &wi_local);
+        Vec3::new(coat, coat, coat) + base
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let frame = Self::frame(hit_record, wo);
+        let wo_local = frame.to_local(wo);
+        if wo_local.z() <= 0.0 {
+            return None;
+        }
+        let coat_probability = self.coat_probability(wo_local.z());
+
+        if uc < coat_probability {
+            if self.distribution.effectively_smooth() {
+                let reflectance = fresnel_dielectric(wo_local.z(), self.refractive_index);
+                let wi = Vec3::new(-wo_local.x(), -wo_local.y(), wo_local.z());
+                return Some(BsdfSample {
+                    wi: frame.local(&wi),
+                    f: Vec3::new(reflectance, reflectance, reflectance),
+                    pdf: coat_probability,
+                    is_specular: true,
+                });
+            }
+            let wi_local = reflect_about(&wo_local, &self.distribution.sample_wm(&wo_local, u));
+            if wi_local.z() <= 0.0 {
+                return None;
+            }
+            let wi = frame.local(&wi_local);
+            let pdf = self.pdf(hit_record, wo, &wi);
+            if pdf <= 0.0 {
+                return None;
+            }
+            return Some(BsdfSample {
+                wi,
+                f: self.eval(hit_record, wo, &wi),
+                pdf,
+                is_specular: false,
+            });
+        }
+
+        let sample = self.base.sample(hit_record, wo, sampler)?;
+        if sample.is_specular {
+            let scale = self.base_scale(hit_record, &wo_local, &frame.to_local(&sample.wi));
+            return Some(BsdfSample {
+                f: sample.f * scale,
+                pdf: sample.pdf * (1.0 - coat_probability),
+                ..sample
+            });
+        }
+        let pdf = self.pdf(hit_record, wo, &sample.wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            f: self.eval(hit_record, wo, &sample.wi),
+            pdf,
+            ..sample
+        })
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> f32 {
+        let frame = Self::frame(hit_record, wo);
+        let (wo_local, wi_local) = (frame.to_local(wo), frame.to_local(wi));
+        if wo_local.z() <= 0.0 {
+            return 0.0;
+        }
+        let coat_probability = self.coat_probability(wo_local.z());
+        coat_probability * self.coat_pdf(&wo_local, &wi_local)
+            + (1.0 - coat_probability) * self.base.pdf(hit_record, wo, wi)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.effectively_smooth() && self.base.is_specular()
+    }
+
+    fn emitted(&self, hit_record: &HitRecord, wo: &Vec3) -> Vec3 {
+        self.base.emitted(hit_record, wo)
+    }
+
+    fn transmittance(&self, hit_record: &HitRecord, distance: f32) -> Vec3 {
+        self.base.transmittance(hit_record, distance)
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        self.base.medium()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
@@ -0,0 +1,600 @@
+use crate::{
+    bounding_box::BoundingBox,
+    ies::IesProfile,
+    light_bvh::{DirectionCone, LightBounds},
+    object::HitRecord,
+    onb::Onb,
+    scene::Background,
+    utils::{uniform_sample_sphere, uniform_sample_triangle},
+    vec3::Vec3,
+};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+pub struct LightSample {
+    // Unit direction from the shading point towards the sampled point on the light
+    pub wi: Vec3,
+    pub radiance: Vec3,
+    // Probability density of having picked wi, with respect to solid angle at the shading point
+    pub pdf: f32,
+    pub distance: f32,
+}
+
+pub trait Light: Sync + Send {
+    // Picks a point on the light as seen from point, using the two dimensional sample u
+    fn sample_li(&self, point: &Vec3, u: (f32, f32)) -> Option<LightSample>;
+
+    // Solid angle density with which sample_li would pick the direction wi from point. hit_record is where
+    // a ray along wi reached the light's surface, and None for lights at infinity.
+    fn pdf_li(&self, point: &Vec3, wi: &Vec3, hit_record: Option<&HitRecord>) -> f32;
+
+    // Lights at infinity are seen by rays that escape the scene rather than by hitting a surface
+    fn is_infinite(&self) -> bool {
+        false
+    }
+
+    // Lights described by a delta distribution, such as point lights, can only be reached by sampling
+    // them, so their samples always get full weight
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    // Where the light is and which way it emits, for picking lights by their contribution. None for
+    // lights at infinity, which have no position.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+// Emission from the outside of a sphere. Its geometry has to be added to the world separately, as a
+// Sphere with a DiffuseLight material of the same emission linked back to this light with with_light.
+pub struct SphereLight {
+    center: Vec3,
+    radius: f32,
+    emit: Vec3,
+}
+
+impl SphereLight {
+    pub fn new(center: Vec3, radius: f32, emit: Vec3) -> Self {
+        SphereLight { center, radius, emit }
+    }
+
+    // Cosine of the half angle of the cone the sphere fills as seen from point, or None from inside it
+    // where nothing emitted can be seen
+    fn cos_theta_max(&self, point: &Vec3) -> Option<(f32, f32)> {
+        let distance_squared = (self.center - *point).squared_length();
+        let sin2_theta_max = self.radius * self.radius / distance_squared;
+        if sin2_theta_max >= 1.0 {
+            return None;
+        }
+        Some(((1.0 - sin2_theta_max).sqrt(), sin2_theta_max))
+    }
+}
+
+impl Light for SphereLight {
+    // Uniformly samples the cone of directions towards the visible cap, following pbrt's sphere sampling
+    fn sample_li(&self, point: &Vec3, u: (f32, f32)) -> Option<LightSample> {
+        let (cos_theta_max, sin2_theta_max) = self.cos_theta_max(point)?;
+        let to_center = self.center - *point;
+        let center_distance = to_center.length();
+
+        // For very small cones sin^2 is computed directly, as 1 - cos^2 would lose all precision
+        let (sin2_theta, cos_theta) = if sin2_theta_max < 0.00068523 {
+            let sin2_theta = sin2_theta_max * u.0;
+            (sin2_theta, (1.0 - sin2_theta).sqrt())
+        } else {
+            let cos_theta = (cos_theta_max - 1.0) * u.0 + 1.0;
+            ((1.0 - cos_theta * cos_theta).max(0.0), cos_theta)
+        };
+        let sin_theta = sin2_theta.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let frame = Onb::build_from_w(&to_center.scalar_mul(1.0 / center_distance));
+        let wi = frame.local(&Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+
+        // Distance to the nearest intersection of wi with the sphere
+        let distance = center_distance * cos_theta
+            - (self.radius * self.radius - center_distance * center_distance * sin2_theta)
+                .max(0.0)
+                .sqrt();
+        Some(LightSample {
+            wi,
+            radiance: self.emit,
+            pdf: cone_pdf(cos_theta_max, sin2_theta_max),
+            distance,
+        })
+    }
+
+    fn pdf_li(&self, point: &Vec3, _wi: &Vec3, _hit_record: Option<&HitRecord>) -> f32 {
+        match self.cos_theta_max(point) {
+            Some((cos_theta_max, sin2_theta_max)) => cone_pdf(cos_theta_max, sin2_theta_max),
+            None => 0.0,
+        }
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let extent = Vec3::new(self.radius, self.radius, self.radius);
+        Some(LightBounds {
+            bounds: BoundingBox::new(self.center - extent, self.center + extent),
+            phi: self.emit.max_component() * 4.0 * PI * self.radius * self.radius * PI,
+            normals: DirectionCone::entire_sphere(),
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+}
+
+// Uniform density over a cone, with 1 - cos written so it stays accurate for narrow cones
+fn cone_pdf(cos_theta_max: f32, sin2_theta_max: f32) -> f32 {
+    1.0 / (2.0 * PI * sin2_theta_max / (1.0 + cos_theta_max))
+}
+
+// Emission from a parallelogram, sampled uniformly by area. Its geometry is a Rect with a DiffuseLight
+// material of the same emission and sidedness, linked back to this light with with_light.
+pub struct RectLight {
+    corner: Vec3,
+    edge1: Vec3,
+    edge2: Vec3,
+    normal: Vec3,
+    area: f32,
+    emit: Vec3,
+    two_sided: bool,
+}
+
+impl RectLight {
+    pub fn new(corner: Vec3, edge1: Vec3, edge2: Vec3, emit: Vec3, two_sided: bool) -> Self {
+        let n = edge1.cross(&edge2);
+        RectLight {
+            corner,
+            edge1,
+            edge2,
+            normal: n.normalize(),
+            area: n.length(),
+            emit,
+            two_sided,
+        }
+    }
+}
+
+impl Light for RectLight {
+    fn sample_li(&self, point: &Vec3, u: (f32, f32)) -> Option<LightSample> {
+        let light_point = self.corner + self.edge1.scalar_mul(u.0) + self.edge2.scalar_mul(u.1);
+        sample_area(point, &light_point, &self.normal, self.area, self.emit, self.two_sided)
+    }
+
+    fn pdf_li(&self, point: &Vec3, wi: &Vec3, hit_record: Option<&HitRecord>) -> f32 {
+        area_pdf(point, wi, hit_record, self.area, self.two_sided)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let corners = [
+            self.corner,
+            self.corner + self.edge1,
+            self.corner + self.edge2,
+            self.corner + self.edge1 + self.edge2,
+        ];
+        Some(area_light_bounds(
+            BoundingBox::from_points(&corners),
+            self.area,
+            self.emit,
+            DirectionCone::new(self.normal, 1.0),
+            self.two_sided,
+        ))
+    }
+}
+
+// Emission from every triangle of a mesh. A triangle is picked in proportion to its area and then
+// sampled uniformly, so points are uniform over the whole surface. Its geometry is a TriangleMesh with a
+// DiffuseLight material of the same emission and sidedness, linked back to this light with with_light.
+pub struct MeshLight {
+    triangles: Vec<[Vec3; 3]>,
+    // Running total of the triangle areas, normalized to end at one
+    area_cdf: Vec<f32>,
+    area: f32,
+    emit: Vec3,
+    two_sided: bool,
+}
+
+impl MeshLight {
+    pub fn new(positions: &[Vec3], indices: &[[usize; 3]], emit: Vec3, two_sided: bool) -> Self {
+        let triangles: Vec<[Vec3; 3]> = indices
+            .iter()
+            .map(|triangle| triangle.map(|vertex| positions[vertex]))
+            .collect();
+        let mut area = 0.0;
+        let mut area_cdf: Vec<f32> = triangles
+            .iter()
+            .map(|[p0, p1, p2]| {
+                area += 0.5 * (*p1 - *p0).cross(&(*p2 - *p0)).length();
+                area
+            })
+            .collect();
+        for value in &mut area_cdf {
+            *value /= area;
+        }
+        MeshLight {
+            triangles,
+            area_cdf,
+            area,
+            emit,
+            two_sided,
+        }
+    }
+}
+
+impl Light for MeshLight {
+    fn sample_li(&self, point: &Vec3, u: (f32, f32)) -> Option<LightSample> {
+        if self.area <= 0.0 {
+            return None;
+        }
+        // The first dimension picks the triangle and is then stretched back over [0, 1) to sample it
+        let index = self.area_cdf.partition_point(|&value| value <= u.0).min(self.triangles.len() - 1);
+        let lower = if index == 0 { 0.0 } else { self.area_cdf[index - 1] };
+        let u0 = ((u.0 - lower) / (self.area_cdf[index] - lower)).min(1.0 - f32::EPSILON);
+        let (b0, b1) = uniform_sample_triangle((u0, u.1));
+        let [p0, p1, p2] = self.triangles[index];
+        let light_point = p0.scalar_mul(b0) + p1.scalar_mul(b1) + p2.scalar_mul(1.0 - b0 - b1);
+        let normal = (p1 - p0).cross(&(p2 - p0)).normalize();
+        sample_area(point, &light_point, &normal, self.area, self.emit, self.two_sided)
+    }
+
+    fn pdf_li(&self, point: &Vec3, wi: &Vec3, hit_record: Option<&HitRecord>) -> f32 {
+        area_pdf(point, wi, hit_record, self.area, self.two_sided)
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let points: Vec<Vec3> = self.triangles.iter().flatten().copied().collect();
+        let normals = self
+            .triangles
+            .iter()
+            .map(|[p0, p1, p2]| (*p1 - *p0).cross(&(*p2 - *p0)))
+            .filter(|normal| normal.squared_length() > 0.0)
+            .map(|normal| DirectionCone::new(normal, 1.0))
+            .reduce(|cone, normal| cone.union(&normal))
+            .unwrap_or_else(DirectionCone::entire_sphere);
+        Some(area_light_bounds(
+            BoundingBox::from_points(&points),
+            self.area,
+            self.emit,
+            normals,
+            self.two_sided,
+        ))
+    }
+}
+
+// Bounds of a diffuse emitter, which sends light out over the hemisphere around each of its normals
+fn area_light_bounds(bounds: BoundingBox, area: f32, emit: Vec3, normals: DirectionCone, two_sided: bool) -> LightBounds {
+    let sides = if two_sided { 2.0 } else { 1.0 };
+    LightBounds {
+        bounds,
+        phi: emit.max_component() * area * PI * sides,
+        normals,
+        cos_theta_e: 0.0,
+        two_sided,
+    }
+}
+
+// Light sample for a point picked uniformly on a surface of the given area, converting the area density
+// to solid angle
+fn sample_area(
+    point: &Vec3,
+    light_point: &Vec3,
+    normal: &Vec3,
+    area: f32,
+    emit: Vec3,
+    two_sided: bool,
+) -> Option<LightSample> {
+    let (wi, distance) = direction_to(point, light_point)?;
+    let cos_light = -normal.dot(&wi);
+    let cos_light = if two_sided { cos_light.abs() } else { cos_light };
+    if cos_light <= 0.0 {
+        return None;
+    }
+    Some(LightSample {
+        wi,
+        radiance: emit,
+        pdf: distance * distance / (cos_light * area),
+        distance,
+    })
+}
+
+// Solid angle density of sample_area having picked the point a ray along wi hit
+fn area_pdf(point: &Vec3, wi: &Vec3, hit_record: Option<&HitRecord>, area: f32, two_sided: bool) -> f32 {
+    let hit_record = match hit_record {
+        Some(hit_record) => hit_record,
+        None => return 0.0,
+    };
+    let cos_light = -hit_record.geometric_normal.dot(wi);
+    let cos_light = if two_sided { cos_light.abs() } else { cos_light };
+    if cos_light <= 0.0 {
+        return 0.0;
+    }
+    (hit_record.point - *point).squared_length() / (cos_light * area)
+}
+
+// Lights the scene with its background, sampling directions uniformly over the sphere
+pub struct EnvironmentLight {
+    background: Background,
+}
+
+impl EnvironmentLight {
+    pub fn new(background: Background) -> Self {
+        EnvironmentLight { background }
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample_li(&self, _point: &Vec3, u: (f32, f32)) -> Option<LightSample> {
+        let wi = uniform_sample_sphere(u);
+        Some(LightSample {
+            wi,
+            radiance: self.background.radiance(&wi),
+            pdf: 1.0 / (4.0 * PI),
+            distance: f32::MAX,
+        })
+    }
+
+    fn pdf_li(&self, _point: &Vec3, _wi: &Vec3, _hit_record: Option<&HitRecord>) -> f32 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn is_infinite(&self) -> bool {
+        true
+    }
+}
+
+// Frame photometric profiles are evaluated in: z along the fixture's straight down, x along its horizontal
+// angle zero and y along 90 degrees, which LM-63 puts counterclockwise from zero seen from above. Horizontal
+// angle zero is horizontal_zero made perpendicular to down, or +z when the two are parallel.
+fn photometric_frame(down: &Vec3, horizontal_zero: &Vec3) -> Onb {
+    let perpendicular = |w: &Vec3| *w - down.scalar_mul(down.dot(w));
+    let u = perpendicular(horizontal_zero);
+    let u = if u.squared_length() < 1e-8 * horizontal_zero.squared_length() {
+        perpendicular(&Vec3::new(0.0, 0.0, 1.0))
+    } else {
+        u
+    }
+    .normalize();
+    Onb { u, v: u.cross(down), w: *down }
+}
+
+// Emits in all directions from a single point. Intensity is in watts per steradian and falls off with the
+// inverse square of the distance. A photometric profile modulates it, with the profile's straight down
+// pointing down the y axis.
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+    profile: Option<(Arc<IesProfile>, Onb)>,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        PointLight {
+            position,
+            intensity,
+            profile: None,
+        }
+    }
+
+    // Profile with its horizontal angle zero towards horizontal_zero
+    pub fn with_profile(mut self, profile: Arc<IesProfile>, horizontal_zero: &Vec3) -> Self {
+        self.profile = Some((profile, photometric_frame(&Vec3::new(0.0, -1.0, 0.0), horizontal_zero)));
+        self
+    }
+
+    // Scales the intensity so the light emits a total power in watts, given after any profile
+    pub fn with_power(mut self, power: Vec3) -> Self {
+        let solid_angle = match &self.profile {
+            Some((profile, _)) => integrate_cone(PI, |w| profile.evaluate(w)),
+            None => 4.0 * PI,
+        };
+        self.intensity = power.scalar_mul(1.0 / solid_angle);
+        self
+    }
+
+    // Relative intensity in the unit direction w leaving the light
+    fn distribution(&self, w: &Vec3) -> f32 {
+        match &self.profile {
+            Some((profile, frame)) => profile.evaluate(&frame.to_local(w)),
+            None => 1.0,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample_li(&self, point: &Vec3, _u: (f32, f32)) -> Option<LightSample> {
+        let (wi, distance) = direction_to(point, &self.position)?;
+        let distribution = self.distribution(&-wi);
+        if distribution == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            radiance: self.intensity.scalar_mul(distribution / (distance * distance)),
+            pdf: 1.0,
+            distance,
+        })
+    }
+
+    fn pdf_li(&self, _point: &Vec3, _wi: &Vec3, _hit_record: Option<&HitRecord>) -> f32 {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: BoundingBox::new(self.position, self.position),
+            phi: 4.0 * PI * self.intensity.max_component(),
+            normals: DirectionCone::entire_sphere(),
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+}
+
+// Point light restricted to a cone, at full intensity inside the inner angle and smoothly falling off
+// to nothing at the outer angle. A photometric profile modulates it, with the profile's straight down
+// along the spot's direction.
+pub struct SpotLight {
+    position: Vec3,
+    direction: Vec3,
+    intensity: Vec3,
+    cos_inner: f32,
+    cos_outer: f32,
+    profile: Option<(Arc<IesProfile>, Onb)>,
+}
+
+impl SpotLight {
+    // Angles are the half angles of the cones in radians
+    pub fn new(position: Vec3, direction: Vec3, intensity: Vec3, inner_angle: f32, outer_angle: f32) -> Self {
+        let outer_angle = outer_angle.max(inner_angle);
+        SpotLight {
+            position,
+            direction: direction.normalize(),
+            intensity,
+            cos_inner: inner_angle.cos(),
+            cos_outer: outer_angle.cos(),
+            profile: None,
+        }
+    }
+
+    // Profile with its horizontal angle zero towards horizontal_zero
+    pub fn with_profile(mut self, profile: Arc<IesProfile>, horizontal_zero: &Vec3) -> Self {
+        self.profile = Some((profile, photometric_frame(&self.direction, horizontal_zero)));
+        self
+    }
+
+    // Scales the intensity so the light emits a total power in watts, spread over its cone and shaped by
+    // any profile given before
+    pub fn with_power(mut self, power: Vec3) -> Self {
+        let solid_angle = match &self.profile {
+            Some((profile, _)) => integrate_cone(self.cos_outer.acos(), |w| self.falloff(w.z()) * profile.evaluate(w)),
+            None => 2.0 * PI * (1.0 - 0.5 * (self.cos_inner + self.cos_outer)),
+        };
+        self.intensity = power.scalar_mul(1.0 / solid_angle);
+        self
+    }
+
+    fn falloff(&self, cos_theta: f32) -> f32 {
+        if cos_theta >= self.cos_inner {
+            1.0
+        } else if cos_theta <= self.cos_outer {
+            0.0
+        } else {
+            let t = (cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+
+    // Relative intensity in the unit direction w leaving the light
+    fn distribution(&self, w: &Vec3) -> f32 {
+        let falloff = self.falloff(w.dot(&self.direction));
+        match &self.profile {
+            Some((profile, frame)) if falloff > 0.0 => falloff * profile.evaluate(&frame.to_local(w)),
+            _ => falloff,
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample_li(&self, point: &Vec3, _u: (f32, f32)) -> Option<LightSample> {
+        let (wi, distance) = direction_to(point, &self.position)?;
+        let distribution = self.distribution(&-wi);
+        if distribution == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            radiance: self.intensity.scalar_mul(distribution / (distance * distance)),
+            pdf: 1.0,
+            distance,
+        })
+    }
+
+    fn pdf_li(&self, _point: &Vec3, _wi: &Vec3, _hit_record: Option<&HitRecord>) -> f32 {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    // Full intensity within the inner cone, and the falloff out to the outer cone as the spread
+    fn bounds(&self) -> Option<LightBounds> {
+        let cos_theta_e = (self.cos_outer.acos() - self.cos_inner.acos()).cos();
+        Some(LightBounds {
+            bounds: BoundingBox::new(self.position, self.position),
+            phi: 4.0 * PI * self.intensity.max_component(),
+            normals: DirectionCone::new(self.direction, self.cos_inner),
+            cos_theta_e,
+            two_sided: false,
+        })
+    }
+}
+
+// Numerical integral over the directions within max_theta of the z axis. Steps are taken in angle
+// rather than cosine so narrow cones are still resolved.
+fn integrate_cone(max_theta: f32, f: impl Fn(&Vec3) -> f32) -> f32 {
+    const THETA_STEPS: usize = 256;
+    const PHI_STEPS: usize = 128;
+    let d_theta = max_theta / THETA_STEPS as f32;
+    let d_phi = 2.0 * PI / PHI_STEPS as f32;
+    let mut total = 0.0;
+    for i in 0..THETA_STEPS {
+        let (sin_theta, cos_theta) = ((i as f32 + 0.5) * d_theta).sin_cos();
+        for j in 0..PHI_STEPS {
+            let (sin_phi, cos_phi) = ((j as f32 + 0.5) * d_phi).sin_cos();
+            let w = Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
+            total += f(&w) * sin_theta;
+        }
+    }
+    total * d_theta * d_phi
+}
+
+// Parallel light arriving from infinitely far away, such as sunlight. Irradiance is in watts per square
+// metre on a surface facing the light.
+pub struct DirectionalLight {
+    // Direction the light travels in
+    direction: Vec3,
+    irradiance: Vec3,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3) -> Self {
+        DirectionalLight {
+            direction: direction.normalize(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample_li(&self, _point: &Vec3, _u: (f32, f32)) -> Option<LightSample> {
+        Some(LightSample {
+            wi: -self.direction,
+            radiance: self.irradiance,
+            pdf: 1.0,
+            distance: f32::MAX,
+        })
+    }
+
+    fn pdf_li(&self, _point: &Vec3, _wi: &Vec3, _hit_record: Option<&HitRecord>) -> f32 {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+// Unit direction and distance from point to target
+fn direction_to(point: &Vec3, target: &Vec3) -> Option<(Vec3, f32)> {
+    let to_target = *target - *point;
+    let distance = to_target.length();
+    if distance == 0.0 {
+        return None;
+    }
+    Some((to_target.scalar_mul(1.0 / distance), distance))
+}
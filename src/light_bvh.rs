@@ -0,0 +1,351 @@
+// Picks lights in proportion to an estimate of how much they contribute at a shading point, using a
+// BVH over the lights' spatial bounds and emission cones. Follows the light BVH of pbrt-v4, which builds
+// on Conty Estevez and Kulla 2018.
+use crate::{bounding_box::BoundingBox, light::Light, vec3::Vec3};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+// Cone of directions around the unit axis w, containing everything within acos(cos_theta) of it
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionCone {
+    pub w: Vec3,
+    pub cos_theta: f32,
+}
+
+impl DirectionCone {
+    pub fn new(w: Vec3, cos_theta: f32) -> Self {
+        DirectionCone { w: w.normalize(), cos_theta }
+    }
+
+    pub fn entire_sphere() -> Self {
+        DirectionCone {
+            w: Vec3::new(0.0, 0.0, 1.0),
+            cos_theta: -1.0,
+        }
+    }
+
+    // Smallest cone containing both
+    pub fn union(&self, other: &DirectionCone) -> DirectionCone {
+        let theta_a = safe_acos(self.cos_theta);
+        let theta_b = safe_acos(other.cos_theta);
+        let theta_d = angle_between(&self.w, &other.w);
+        if (theta_d + theta_b).min(PI) <= theta_a {
+            return *self;
+        }
+        if (theta_d + theta_a).min(PI) <= theta_b {
+            return *other;
+        }
+
+        let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+        if theta_o >= PI {
+            return DirectionCone::entire_sphere();
+        }
+        // Rotate self's axis towards other's so the new cone just touches both
+        let theta_r = theta_o - theta_a;
+        let axis = self.w.cross(&other.w);
+        if axis.squared_length() == 0.0 {
+            return DirectionCone::entire_sphere();
+        }
+        let w = rotate(&self.w, &axis.normalize(), theta_r);
+        DirectionCone::new(w, theta_o.cos())
+    }
+}
+
+// What the light BVH knows about a light or group of lights: where it is, how much it emits and in
+// which directions. Emission leaves along directions within the normal cone widened by acos(cos_theta_e).
+#[derive(Clone, Debug)]
+pub struct LightBounds {
+    pub bounds: BoundingBox,
+    pub phi: f32,
+    pub normals: DirectionCone,
+    pub cos_theta_e: f32,
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    fn union(&self, other: &LightBounds) -> LightBounds {
+        LightBounds {
+            bounds: BoundingBox::containing_box(&self.bounds, &other.bounds),
+            phi: self.phi + other.phi,
+            normals: self.normals.union(&other.normals),
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    fn centroid(&self) -> Vec3 {
+        (self.bounds.min + self.bounds.max).scalar_mul(0.5)
+    }
+
+    // Conservative estimate of the light reaching point, a surface with the given normal. Takes the
+    // smallest angles the bounds allow between the emission cone, the direction to the point and the
+    // normal.
+    fn importance(&self, point: &Vec3, normal: &Vec3) -> f32 {
+        let centroid = self.centroid();
+        let diagonal = self.bounds.max - self.bounds.min;
+        let distance_squared = (*point - centroid).squared_length().max(diagonal.length() / 2.0);
+
+        let cos_sub_clamped = |sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32| {
+            if cos_a > cos_b {
+                1.0
+            } else {
+                cos_a * cos_b + sin_a * sin_b
+            }
+        };
+        let sin_sub_clamped = |sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32| {
+            if cos_a > cos_b {
+                0.0
+            } else {
+                sin_a * cos_b - cos_a * sin_b
+            }
+        };
+
+        // Angle between the direction from the light to the point and the emission axis
+        let wi = (*point - centroid).normalize();
+        let mut cos_theta_w = self.normals.w.dot(&wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+
+        // Half angle of the cone the bounds fill as seen from the point
+        let cos_theta_b = bound_subtended_cos_theta(&self.bounds, point);
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        let cos_theta_o = self.normals.cos_theta;
+        let sin_theta_o = safe_sqrt(1.0 - cos_theta_o * cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.phi * cos_theta_p / distance_squared;
+        if normal.squared_length() > 0.0 {
+            let cos_theta_i = wi.dot(normal).abs();
+            let sin_theta_i = safe_sqrt(1.0 - cos_theta_i * cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+        importance.max(0.0)
+    }
+}
+
+enum LightNode {
+    Leaf { bounds: LightBounds, light: usize },
+    // The first child directly follows its parent
+    Interior { bounds: LightBounds, second_child: usize },
+}
+
+impl LightNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightNode::Leaf { bounds, .. } | LightNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+pub struct LightBvh {
+    lights: Vec<Arc<dyn Light>>,
+    // Lights without bounds, such as those at infinity, which are picked uniformly
+    infinite_lights: Vec<usize>,
+    nodes: Vec<LightNode>,
+    // Path from the root to each bounded light's leaf, keyed by the light's address. Bit i is set when the
+    // second child is taken at depth i.
+    bit_trails: HashMap<usize, u64>,
+}
+
+impl LightBvh {
+    pub fn new(lights: &[Arc<dyn Light>]) -> Self {
+        let mut bvh = LightBvh {
+            lights: lights.to_vec(),
+            infinite_lights: vec![],
+            nodes: vec![],
+            bit_trails: HashMap::new(),
+        };
+        let mut bounded = vec![];
+        for (index, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) if bounds.phi > 0.0 => bounded.push((index, bounds)),
+                Some(_) => {}
+                None => bvh.infinite_lights.push(index),
+            }
+        }
+        if !bounded.is_empty() {
+            bvh.build(&mut bounded, 0, 0);
+        }
+        bvh
+    }
+
+    // Appends the subtree over lights to the node list, returning the bounds of its root
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], bit_trail: u64, depth: u32) -> LightBounds {
+        if lights.len() == 1 {
+            let (light, bounds) = lights[0].clone();
+            self.bit_trails.insert(address(self.lights[light].as_ref()), bit_trail);
+            self.nodes.push(LightNode::Leaf {
+                bounds: bounds.clone(),
+                light,
+            });
+            return bounds;
+        }
+
+        // Split in half along the axis the centroids are most spread out on
+        let centroids = lights.iter().map(|(_, bounds)| bounds.centroid()).collect::<Vec<_>>();
+        let spread = BoundingBox::from_points(&centroids);
+        let extent = spread.max - spread.min;
+        // Total ordering so lights with non-finite bounds still sort instead of panicking
+        let axis = (0..3).max_by(|&a, &b| extent.data()[a].total_cmp(&extent.data()[b])).unwrap();
+        lights.sort_by(|(_, a), (_, b)| a.centroid().data()[axis].total_cmp(&b.centroid().data()[axis]));
+        let (first, second) = lights.split_at_mut(lights.len() / 2);
+
+        let node_index = self.nodes.len();
+        self.nodes.push(LightNode::Leaf {
+            bounds: first[0].1.clone(),
+            light: 0,
+        });
+        let first_bounds = self.build(first, bit_trail, depth + 1);
+        let second_child = self.nodes.len();
+        let second_bounds = self.build(second, bit_trail | (1 << depth), depth + 1);
+        let bounds = first_bounds.union(&second_bounds);
+        self.nodes[node_index] = LightNode::Interior {
+            bounds: bounds.clone(),
+            second_child,
+        };
+        bounds
+    }
+
+    // Chance of picking one of the bounded lights rather than one at infinity
+    fn bounded_probability(&self) -> f32 {
+        let bounded = if self.nodes.is_empty() { 0.0 } else { 1.0 };
+        bounded / (self.infinite_lights.len() as f32 + bounded)
+    }
+
+    // Picks a light to sample for the surface at point with the given normal, returning it with the
+    // probability it had of being chosen
+    pub fn sample(&self, point: &Vec3, normal: &Vec3, u: f32) -> Option<(&dyn Light, f32)> {
+        let p_bounded = self.bounded_probability();
+        let p_infinite = 1.0 - p_bounded;
+        if u < p_infinite {
+            let count = self.infinite_lights.len();
+            let index = ((u / p_infinite * count as f32) as usize).min(count - 1);
+            return Some((self.lights[self.infinite_lights[index]].as_ref(), p_infinite / count as f32));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut u = ((u - p_infinite) / p_bounded).min(1.0 - f32::EPSILON);
+        let mut pmf = p_bounded;
+        let mut node_index = 0;
+        loop {
+            match &self.nodes[node_index] {
+                LightNode::Interior { second_child, .. } => {
+                    let first = self.nodes[node_index + 1].bounds().importance(point, normal);
+                    let second = self.nodes[*second_child].bounds().importance(point, normal);
+                    if first == 0.0 && second == 0.0 {
+                        return None;
+                    }
+                    let p_first = first / (first + second);
+                    if u < p_first {
+                        node_index += 1;
+                        u = (u / p_first).min(1.0 - f32::EPSILON);
+                        pmf *= p_first;
+                    } else {
+                        node_index = *second_child;
+                        u = ((u - p_first) / (1.0 - p_first)).min(1.0 - f32::EPSILON);
+                        pmf *= 1.0 - p_first;
+                    }
+                }
+                LightNode::Leaf { bounds, light } => {
+                    if node_index > 0 || bounds.importance(point, normal) > 0.0 {
+                        return Some((self.lights[*light].as_ref(), pmf));
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+
+    // Probability of sample picking light for the surface at point with the given normal
+    pub fn pmf(&self, point: &Vec3, normal: &Vec3, light: &dyn Light) -> f32 {
+        let light_address = address(light);
+        let mut bit_trail = match self.bit_trails.get(&light_address) {
+            Some(&bit_trail) => bit_trail,
+            None => {
+                let is_infinite = self
+                    .infinite_lights
+                    .iter()
+                    .any(|&index| address(self.lights[index].as_ref()) == light_address);
+                if !is_infinite {
+                    return 0.0;
+                }
+                return (1.0 - self.bounded_probability()) / self.infinite_lights.len() as f32;
+            }
+        };
+
+        let mut pmf = self.bounded_probability();
+        let mut node_index = 0;
+        while let LightNode::Interior { second_child, .. } = &self.nodes[node_index] {
+            let first = self.nodes[node_index + 1].bounds().importance(point, normal);
+            let second = self.nodes[*second_child].bounds().importance(point, normal);
+            if first == 0.0 && second == 0.0 {
+                return 0.0;
+            }
+            if bit_trail & 1 == 0 {
+                pmf *= first / (first + second);
+                node_index += 1;
+            } else {
+                pmf *= second / (first + second);
+                node_index = *second_child;
+            }
+            bit_trail >>= 1;
+        }
+        pmf
+    }
+}
+
+// Identifies a light by where it lives, which is the same for the Arc in the scene's list and the
+// reference a hit record carries
+fn address(light: &dyn Light) -> usize {
+    light as *const dyn Light as *const () as usize
+}
+
+// Cosine of the half angle of the cone around the direction to the box's centre containing the box's
+// bounding sphere, as seen from point. -1 from inside the sphere.
+fn bound_subtended_cos_theta(bounds: &BoundingBox, point: &Vec3) -> f32 {
+    let center = (bounds.min + bounds.max).scalar_mul(0.5);
+    let radius_squared = (bounds.max - center).squared_length();
+    let distance_squared = (*point - center).squared_length();
+    if distance_squared < radius_squared {
+        return -1.0;
+    }
+    safe_sqrt(1.0 - radius_squared / distance_squared)
+}
+
+// Rotation of v by theta around the unit axis, by Rodrigues' formula
+fn rotate(v: &Vec3, axis: &Vec3, theta: f32) -> Vec3 {
+    let (sin, cos) = theta.sin_cos();
+    v.scalar_mul(cos) + axis.cross(v).scalar_mul(sin) + axis.scalar_mul(axis.dot(v) * (1.0 - cos))
+}
+
+// Angle between unit vectors, accurate even when they are nearly parallel
+fn angle_between(a: &Vec3, b: &Vec3) -> f32 {
+    if a.dot(b) < 0.0 {
+        PI - 2.0 * safe_asin((*a + *b).length() / 2.0)
+    } else {
+        2.0 * safe_asin((*b - *a).length() / 2.0)
+    }
+}
+
+fn safe_sqrt(x: f32) -> f32 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_acos(x: f32) -> f32 {
+    x.clamp(-1.0, 1.0).acos()
+}
+
+fn safe_asin(x: f32) -> f32 {
+    x.clamp(-1.0, 1.0).asin()
+}
@@ -2,89 +2,97 @@
 mod camera;
 mod material;
 mod object;
+mod onb;
 mod ray;
 mod sphere;
 mod utils;
 mod vec3;
 mod bounding_box;
+mod bump;
 mod bvh_node;
+mod film;
+mod filter;
+mod ies;
+mod integrator;
+mod light;
+mod light_bvh;
+mod medium;
+mod microfacet;
+mod render;
+mod rng;
+mod sampler;
+mod scene;
+mod scene_file;
+mod spectrum;
+mod principled;
+mod subsurface;
+mod texture;
+mod layered;
+mod tile;
+mod rect;
+mod triangle;
 
 use rand::prelude::*;
 use std::fs::File;
-use std::io::{Error, Write};
-use rayon::prelude::*;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
 use std::time::Instant;
 
-use crate::camera::Camera;
-use crate::material::{Dielectric, Lambertian, Metal};
+use crate::camera::CameraSettings;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
 use crate::object::{Object};
-use crate::ray::Ray;
+use crate::filter::FilterKind;
+use crate::integrator::IntegratorKind;
+use crate::integrator::{Heuristic, PathDepth};
+use crate::light::{EnvironmentLight, Light, SphereLight};
+use crate::render::{render, RenderSettings};
+use crate::rng::Pcg32;
+use crate::sampler::SamplerKind;
+use crate::scene::{Background, Scene};
+use crate::scene_file::load_scene;
+use crate::spectrum::ColorMode;
 use crate::sphere::{Sphere, MovingSphere};
+use crate::tile::TileOrder;
 use crate::vec3::Vec3;
-use crate::bvh_node::BvhNode;
 
-fn get_color(ray: &Ray, world: &dyn Object, depth: u32) -> Vec3 {
-    if let Some(hit_record) = world.hit(ray, 0.001, std::f32::MAX) {
-        if depth < 50 {
-            if let Some((attenuation, scattered)) = hit_record.material.scatter(ray, &hit_record) {
-                return attenuation * get_color(&scattered, world, depth + 1);
-            }
-        }
-        Vec3::new(0.0, 0.0, 0.0)
-    } else {
-        let unit_direction = ray.direction().normalize();
-        let t = 0.5 * (unit_direction.y() + 1.0);
-        Vec3::new(1.0, 1.0, 1.0).lerp(&Vec3::new(0.5, 0.7, 1.0), t)
-    }
+struct Options {
+    settings: RenderSettings,
+    scene: String,
+    output_path: String,
+    sample_count_path: Option<String>,
 }
 
 fn main() -> Result<(), Error> {
     let now = Instant::now();
-    let path = "output.ppm";
-    let mut output = File::create(path)?;
-    let nx = 1200;
-    let ny = 800;
-    let ns = 50;
-    write!(output, "P3\n{} {} \n255\n", nx, ny)?;
-
-    let look_from = Vec3::new(13.0, 2.0, 3.0);
-    let look_at = Vec3::new(0.0, 0.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
+    let options = parse_args()?;
+    let settings = &options.settings;
+    let mut output = File::create(&options.output_path)?;
 
-    let camera = Camera::new(
-        look_from,
-        look_at,
-        Vec3::new(0.0, 1.0, 0.0),
-        std::f32::consts::PI / 9.0,
-        nx as f32 / ny as f32,
-        aperture,
-        dist_to_focus,
-        0.0,
-        1.0
-    );
-
-
-    //let world = test_scene();
-    let world = random_scene();
+    let mut camera_settings = CameraSettings::default();
+    let scene = match options.scene.as_str() {
+        "test" => test_scene(settings.seed),
+        "lights" => lights_scene(settings.seed),
+        "random" => random_scene(settings.seed),
+        // Anything else is the path of a scene file
+        path => {
+            let description = load_scene(path, settings.seed)?;
+            if let Some(camera) = description.camera {
+                camera_settings = camera;
+            }
+            description.scene
+        }
+    };
+    let camera = camera_settings.build(settings.width as f32 / settings.height as f32);
     println!("Scene created after {}s", now.elapsed().as_secs());
 
-    for j in (0..ny).rev() {
-        for i in 0..nx {
-            let mut color: Vec3 = (0..ns).into_par_iter().map(|_| {
-                let mut rng = thread_rng();
-                let u = (i as f32 + rng.gen::<f32>()) / nx as f32;
-                let v = (j as f32 + rng.gen::<f32>()) / ny as f32;
-                let ray = camera.get_ray(u, v);
-                get_color(&ray, &world, 0)
-            }).sum();
-            color = color.scalar_mul(1.0 / ns as f32);
-            color = Vec3::new(color.x().sqrt(), color.y().sqrt(), color.z().sqrt());
-            let ir = (255.99 * color.r()) as i32;
-            let ig = (255.99 * color.g()) as i32;
-            let ib = (255.99 * color.b()) as i32;
-            writeln!(output, "{} {} {}", ir, ig, ib)?;
-        }
+    let film = render(&camera, &scene, settings);
+    if options.output_path.ends_with(".pfm") {
+        film.write_pfm(&mut output)?;
+    } else {
+        film.write_ppm(&mut output)?;
+    }
+    if let Some(sample_count_path) = &options.sample_count_path {
+        film.write_sample_count_ppm(&mut File::create(sample_count_path)?)?;
     }
 
     println!("Completed {}s", now.elapsed().as_secs());
@@ -92,38 +100,109 @@ fn main() -> Result<(), Error> {
     Ok(())
 }
 
-fn test_scene() -> BvhNode {
-    let mut world: Vec<Box<dyn Object>> = vec![];
-    world.push(Box::new(Sphere::new(
-        Vec3::new(0.0, 0.0, -1.0),
-        0.5,
-        Box::new(Lambertian::new(Vec3::new(0.1, 1.0, 1.0))),
-    )));
-    world.push(Box::new(Sphere::new(
-        Vec3::new(0.0, -100.5, -1.0),
-        100.0,
-        Box::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.0))),
-    )));
-    world.push(Box::new(Sphere::new(
-        Vec3::new(1.0, 0.0, -1.0),
-        0.5,
-        Box::new(Metal::new(Vec3::new(0.8, 0.6, 0.2), 0.0)),
-    )));
-    world.push(Box::new(Sphere::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        0.5,
-        Box::new(Dielectric::new(1.5)),
-    )));
-    world.push(Box::new(Sphere::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        -0.45,
-        Box::new(Dielectric::new(1.5)),
-    )));
-    BvhNode::new(world)
+fn parse_args() -> Result<Options, Error> {
+    let mut options = Options {
+        settings: RenderSettings {
+            width: 1200,
+            height: 800,
+            samples_per_pixel: 50,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            seed: 0,
+            sampler: SamplerKind::Sobol,
+            filter: FilterKind::Mitchell,
+            integrator: IntegratorKind::Mis(Heuristic::Power),
+            path_depth: PathDepth::default(),
+            color_mode: ColorMode::Rgb,
+            adaptive_threshold: None,
+            min_samples_per_pixel: 16,
+        },
+        scene: "random".to_string(),
+        output_path: "output.ppm".to_string(),
+        sample_count_path: None,
+    };
+    let settings = &mut options.settings;
+    let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+    let parse_depth = |value: &str| value.parse().map_err(|_| invalid(format!("Invalid depth '{}'", value)));
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| invalid(format!("Missing value for {}", flag)))?;
+        match flag.as_str() {
+            "--width" => settings.width = value.parse().map_err(|_| invalid(format!("Invalid width '{}'", value)))?,
+            "--height" => settings.height = value.parse().map_err(|_| invalid(format!("Invalid height '{}'", value)))?,
+            "--samples" => {
+                settings.samples_per_pixel = value.parse().map_err(|_| invalid(format!("Invalid sample count '{}'", value)))?
+            }
+            "--tile-size" => settings.tile_size = value.parse().map_err(|_| invalid(format!("Invalid tile size '{}'", value)))?,
+            "--tile-order" => settings.tile_order = value.parse().map_err(invalid)?,
+            "--sampler" => settings.sampler = value.parse().map_err(invalid)?,
+            "--adaptive-threshold" => {
+                settings.adaptive_threshold =
+                    Some(value.parse().map_err(|_| invalid(format!("Invalid adaptive threshold '{}'", value)))?)
+            }
+            "--min-samples" => {
+                settings.min_samples_per_pixel =
+                    value.parse().map_err(|_| invalid(format!("Invalid sample count '{}'", value)))?
+            }
+            "--filter" => settings.filter = value.parse().map_err(invalid)?,
+            "--integrator" => settings.integrator = value.parse().map_err(invalid)?,
+            "--color-mode" => settings.color_mode = value.parse().map_err(invalid)?,
+            "--max-depth" => settings.path_depth.max_depth = parse_depth(&value)?,
+            "--max-diffuse-depth" => settings.path_depth.max_diffuse = parse_depth(&value)?,
+            "--max-specular-depth" => settings.path_depth.max_specular = parse_depth(&value)?,
+            "--max-transmission-depth" => settings.path_depth.max_transmission = parse_depth(&value)?,
+            "--max-volume-depth" => settings.path_depth.max_volume = parse_depth(&value)?,
+            "--russian-roulette-depth" => settings.path_depth.russian_roulette_depth = parse_depth(&value)?,
+            "--scene" => options.scene = value,
+            "--output" => options.output_path = value,
+            "--sample-count-output" => options.sample_count_path = Some(value),
+            "--seed" => settings.seed = value.parse().map_err(|_| invalid(format!("Invalid seed '{}'", value)))?,
+            _ => return Err(invalid(format!("Unknown argument {}", flag))),
+        }
+    }
+    Ok(options)
+}
+
+fn sky() -> Background {
+    Background::Gradient(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.7, 1.0))
 }
 
-fn random_scene() -> BvhNode {
-    let mut rng = thread_rng();
+fn test_scene(seed: u64) -> Scene {
+    let mut rng = Pcg32::new(seed, 0);
+    let world: Vec<Box<dyn Object>> = vec![
+        Box::new(Sphere::new(
+            Vec3::new(0.0, 0.0, -1.0),
+            0.5,
+            Box::new(Lambertian::new(Vec3::new(0.1, 1.0, 1.0))),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(0.0, -100.5, -1.0),
+            100.0,
+            Box::new(Lambertian::new(Vec3::new(0.8, 0.8, 0.0))),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(1.0, 0.0, -1.0),
+            0.5,
+            Box::new(Metal::new(Vec3::new(0.8, 0.6, 0.2), 0.0)),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            0.5,
+            Box::new(Dielectric::new(1.5)),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            -0.45,
+            Box::new(Dielectric::new(1.5)),
+        )),
+    ];
+    Scene::new(world, vec![Arc::new(EnvironmentLight::new(sky()))], sky(), &mut rng)
+}
+
+fn random_scene(seed: u64) -> Scene {
+    let mut rng = Pcg32::new(seed, 0);
     let mut world: Vec<Box<dyn Object>> = vec![];
     world.push(Box::new(Sphere::new(
         Vec3::new(0.0, -1000.0, 0.0),
@@ -193,5 +272,39 @@ fn random_scene() -> BvhNode {
         1.0,
         Box::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0)),
     )));
-    BvhNode::new(world)
+    Scene::new(world, vec![Arc::new(EnvironmentLight::new(sky()))], sky(), &mut rng)
+}
+
+fn lights_scene(seed: u64) -> Scene {
+    let mut rng = Pcg32::new(seed, 0);
+    let light_center = Vec3::new(2.0, 4.0, 2.0);
+    let light_radius = 0.3;
+    let light_emit = Vec3::new(60.0, 55.0, 50.0);
+    let light: Arc<dyn Light> = Arc::new(SphereLight::new(light_center, light_radius, light_emit));
+    let world: Vec<Box<dyn Object>> = vec![
+        Box::new(Sphere::new(
+            Vec3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            1.0,
+            Box::new(Dielectric::new(1.5)),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(-4.0, 1.0, 0.0),
+            1.0,
+            Box::new(Lambertian::new(Vec3::new(0.4, 0.2, 0.1))),
+        )),
+        Box::new(Sphere::new(
+            Vec3::new(4.0, 1.0, 0.0),
+            1.0,
+            Box::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0)),
+        )),
+        Box::new(
+            Sphere::new(light_center, light_radius, Box::new(DiffuseLight::new(light_emit))).with_light(light.clone()),
+        ),
+    ];
+    Scene::new(world, vec![light], Background::Constant(Vec3::new(0.01, 0.01, 0.02)), &mut rng)
 }
@@ -1,14 +1,86 @@
 use crate::{
+    medium::Medium,
+    microfacet::{
+        fresnel_complex_rgb, fresnel_dielectric, fresnel_thin_film, half_vector, reflect_about, refract_about,
+        transmission_jacobian, TrowbridgeReitz,
+    },
     object::HitRecord,
-    ray::Ray,
-    utils::{random_in_unit_sphere, reflect, refract, schlick},
+    onb::Onb,
+    sampler::Sampler,
+    texture::Texture,
+    utils::cosine_sample_hemisphere,
     vec3::Vec3,
 };
 
-use rand::Rng;
+use std::f32::consts::PI;
+use std::str::FromStr;
+use std::sync::Arc;
 
-pub trait Material: Sync {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray)>;
+// A direction picked by Material::sample. f is the BSDF times |cos(theta_i)|, so a path's throughput is
+// scaled by f / pdf. Specular samples come from delta lobes: their pdf is the discrete probability of
+// choosing that lobe and they can't be evaluated for any other direction.
+pub struct BsdfSample {
+    pub wi: Vec3,
+    pub f: Vec3,
+    pub pdf: f32,
+    pub is_specular: bool,
+}
+
+// All directions are unit vectors pointing away from the surface: wo back along the incoming ray and wi
+// towards where light arrives from. BSDF values are in the hit's path color, so materials upsample their
+// own colors and can compute wavelength dependent effects at each of the path's wavelengths. Emission and
+// transmittance are RGB.
+pub trait Material: Sync + Send {
+    // BSDF times |cos(theta_i)|, zero for delta lobes
+    fn eval(&self, _hit_record: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    // Samples wi. Implementations draw the same sampler dimensions on every call, whichever lobe is chosen or
+    // whether sampling fails, so the dimensions after it line up across a pixel's samples
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample>;
+
+    // Solid angle density with which sample would return wi, zero for delta lobes
+    fn pdf(&self, _hit_record: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> f32 {
+        0.0
+    }
+
+    // True when the material only has delta lobes, so lighting it by sampling light sources is pointless
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    fn emitted(&self, _hit_record: &HitRecord, _wo: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    // Fraction of light that survives travelling distance to reach the surface, which for materials
+    // enclosing an absorbing medium is less than one when it came from the inside
+    fn transmittance(&self, _hit_record: &HitRecord, _distance: f32) -> Vec3 {
+        Vec3::new(1.0, 1.0, 1.0)
+    }
+
+    // Scattering medium filling the inside of a closed surface, which light reaching the surface from the
+    // inside random walks through
+    fn medium(&self) -> Option<&Medium> {
+        None
+    }
+
+    // Whether scattering depends on wavelength in a way spectral rendering has to follow one wavelength at a
+    // time, such as refraction with an index that varies with wavelength
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+}
+
+// Normal flipped to the side of the surface that wo is on
+#[inline]
+fn facing_normal(hit_record: &HitRecord, wo: &Vec3) -> Vec3 {
+    if hit_record.normal.dot(wo) < 0.0 {
+        -hit_record.normal
+    } else {
+        hit_record.normal
+    }
 }
 
 pub struct Lambertian {
@@ -16,11 +88,31 @@ pub struct Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray)> {
-        let target = hit_record.point + hit_record.normal + random_in_unit_sphere();
-        let scattered = Ray::new_at_time(hit_record.point, target - hit_record.point, ray_in.time());
-        let attenuation = self.albedo;
-        Some((attenuation, scattered))
+    fn eval(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let cosine = facing_normal(hit_record, wo).dot(wi);
+        if cosine <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        hit_record.color.upsample_albedo(self.albedo).scalar_mul(cosine / PI)
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let normal = facing_normal(hit_record, wo);
+        let wi = Onb::build_from_w(&normal).local(&cosine_sample_hemisphere(sampler.get_2d()));
+        let pdf = self.pdf(hit_record, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(hit_record, wo, &wi),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> f32 {
+        facing_normal(hit_record, wo).dot(wi).max(0.0) / PI
     }
 }
 
@@ -30,72 +122,573 @@ impl Lambertian {
     }
 }
 
-pub struct Metal {
+// Rough diffuse surface made of V-shaped Lambertian facets whose slopes have standard deviation sigma,
+// from Oren and Nayar, "Generalization of Lambert's Reflectance Model" (1994). Such surfaces flatten
+// towards the edges and brighten when lit from behind the viewer. By default this is the paper's
+// qualitative model; with interreflection it is the full model, which also includes light bouncing
+// between facets and backscatters more strongly, as moon dust does.
+pub struct OrenNayar {
     albedo: Vec3,
-    fuzz: f32,
+    sigma: f32,
+    interreflection: bool,
+}
+
+impl OrenNayar {
+    // Sigma in radians, where zero is Lambertian
+    pub fn new(albedo: Vec3, sigma: f32) -> Self {
+        OrenNayar {
+            albedo,
+            sigma: sigma.max(0.0),
+            interreflection: false,
+        }
+    }
+
+    pub fn with_interreflection(mut self, interreflection: bool) -> Self {
+        self.interreflection = interreflection;
+        self
+    }
+}
+
+impl Material for OrenNayar {
+    fn eval(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let frame = Onb::build_from_w(&facing_normal(hit_record, wo));
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wi.z() <= 0.0 || wo.z() <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let (theta_i, theta_o) = (wi.z().min(1.0).acos(), wo.z().min(1.0).acos());
+        let (alpha, beta) = (theta_i.max(theta_o), theta_i.min(theta_o));
+        // Cosine of the azimuthal angle between the two directions, zero when either is along the normal
+        let (projected_i, projected_o) = (Vec3::new(wi.x(), wi.y(), 0.0), Vec3::new(wo.x(), wo.y(), 0.0));
+        let lengths = projected_i.length() * projected_o.length();
+        let cos_phi = if lengths > 1e-8 { projected_i.dot(&projected_o) / lengths } else { 0.0 };
+        let sigma2 = self.sigma * self.sigma;
+        let albedo = hit_record.color.upsample_albedo(self.albedo);
+
+        if !self.interreflection {
+            let a = 1.0 - sigma2 / (2.0 * (sigma2 + 0.33));
+            let b = 0.45 * sigma2 / (sigma2 + 0.09);
+            let scale = a + b * cos_phi.max(0.0) * alpha.sin() * beta.tan();
+            return albedo.scalar_mul(scale * wi.z() / PI);
+        }
+
+        let c1 = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+        let c2 = 0.45 * sigma2 / (sigma2 + 0.09)
+            * if cos_phi >= 0.0 { alpha.sin() } else { alpha.sin() - (2.0 * beta / PI).powi(3) };
+        let c3 = 0.125 * sigma2 / (sigma2 + 0.09) * (4.0 * alpha * beta / (PI * PI)).powi(2);
+        let direct = c1 + cos_phi * c2 * beta.tan() + (1.0 - cos_phi.abs()) * c3 * ((alpha + beta) / 2.0).tan();
+        let interreflection = 0.17 * sigma2 / (sigma2 + 0.13) * (1.0 - cos_phi * (2.0 * beta / PI).powi(2));
+        let f = albedo.scalar_mul(direct) + (albedo * albedo).scalar_mul(interreflection);
+        f.scalar_mul(wi.z() / PI)
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let normal = facing_normal(hit_record, wo);
+        let wi = Onb::build_from_w(&normal).local(&cosine_sample_hemisphere(sampler.get_2d()));
+        let pdf = self.pdf(hit_record, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(hit_record, wo, &wi),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> f32 {
+        facing_normal(hit_record, wo).dot(wi).max(0.0) / PI
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetalPreset {
+    Gold,
+    Copper,
+    Aluminum,
+    Silver,
+}
+
+impl MetalPreset {
+    // Complex index of refraction eta + i k at red, green and blue wavelengths
+    pub fn eta_k(self) -> (Vec3, Vec3) {
+        match self {
+            MetalPreset::Gold => (Vec3::new(0.143, 0.374, 1.442), Vec3::new(3.983, 2.385, 1.603)),
+            MetalPreset::Copper => (Vec3::new(0.200, 0.924, 1.102), Vec3::new(3.912, 2.452, 2.142)),
+            MetalPreset::Aluminum => (Vec3::new(1.657, 0.880, 0.521), Vec3::new(9.224, 6.270, 4.837)),
+            MetalPreset::Silver => (Vec3::new(0.155, 0.117, 0.138), Vec3::new(4.828, 3.122, 2.147)),
+        }
+    }
+}
+
+impl FromStr for MetalPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gold" => Ok(MetalPreset::Gold),
+            "copper" => Ok(MetalPreset::Copper),
+            "aluminum" => Ok(MetalPreset::Aluminum),
+            "silver" => Ok(MetalPreset::Silver),
+            _ => Err(format!("Unknown metal '{}'", s)),
+        }
+    }
+}
+
+// Wavelengths in nanometres standing in for red, green and blue when rendering in RGB
+const RGB_WAVELENGTHS: [f32; 3] = [630.0, 532.0, 465.0];
+
+// Value at a wavelength of something given for red, green and blue, interpolated between the
+// representative wavelengths and held constant beyond them
+fn rgb_at_wavelength(value: &Vec3, wavelength: f32) -> f32 {
+    let [red, green, blue] = RGB_WAVELENGTHS;
+    if wavelength >= red {
+        value.r()
+    } else if wavelength >= green {
+        value.g() + (value.r() - value.g()) * (wavelength - green) / (red - green)
+    } else if wavelength >= blue {
+        value.b() + (value.g() - value.b()) * (wavelength - blue) / (green - blue)
+    } else {
+        value.b()
+    }
+}
+
+fn mean(value: &Vec3) -> f32 {
+    (value.r() + value.g() + value.b()) / 3.0
+}
+
+// Transparent coating a fraction of a micron thick, whose reflections off its top and bottom interfere to
+// give the colors of soap bubbles, oil slicks and anodized metal. Thickness is in nanometres.
+#[derive(Clone)]
+pub struct ThinFilm {
+    thickness: Arc<dyn Texture>,
+    refractive_index: f32,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: Arc<dyn Texture>, refractive_index: f32) -> Self {
+        ThinFilm { thickness, refractive_index }
+    }
+
+    // Reflectance of the film between a medium of index incident_eta and a substrate with complex index
+    // eta + i k, given for red, green and blue. Spectral renders evaluate it at each of the path's
+    // wavelengths, RGB renders at a representative wavelength for each channel.
+    fn reflectance(&self, hit_record: &HitRecord, cos_theta_i: f32, incident_eta: f32, eta: Vec3, k: Vec3) -> Vec3 {
+        let thickness = self.thickness.scalar(hit_record).max(0.0);
+        let at = |wavelength: f32| {
+            let (eta, k) = (rgb_at_wavelength(&eta, wavelength), rgb_at_wavelength(&k, wavelength));
+            fresnel_thin_film(cos_theta_i, incident_eta, self.refractive_index, thickness, eta, k, wavelength)
+        };
+        let [first, second, third] = hit_record.color.wavelengths().unwrap_or(RGB_WAVELENGTHS);
+        Vec3::new(at(first), at(second), at(third))
+    }
+}
+
+enum MetalFresnel {
+    // Reflectance at normal incidence, tending to white at grazing angles
+    Schlick(Vec3),
+    Conductor { eta: Vec3, k: Vec3 },
+}
+
+impl MetalFresnel {
+    // Complex index of refraction for each of red, green and blue. For Schlick's approximation it is the
+    // purely imaginary index with the same reflectance at normal incidence.
+    fn eta_k(&self) -> (Vec3, Vec3) {
+        match self {
+            MetalFresnel::Schlick(f0) => {
+                let k = |f0: f32| {
+                    let f0 = f0.clamp(0.0, 0.999);
+                    2.0 * (f0 / (1.0 - f0)).sqrt()
+                };
+                (Vec3::new(1.0, 1.0, 1.0), Vec3::new(k(f0.r()), k(f0.g()), k(f0.b())))
+            }
+            MetalFresnel::Conductor { eta, k } => (*eta, *k),
+        }
+    }
+
+    fn evaluate(&self, cos_theta: f32) -> Vec3 {
+        match self {
+            MetalFresnel::Schlick(f0) => {
+                let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+                *f0 + (Vec3::new(1.0, 1.0, 1.0) - *f0).scalar_mul(weight)
+            }
+            MetalFresnel::Conductor { eta, k } => fresnel_complex_rgb(cos_theta, eta, k),
+        }
+    }
+}
+
+// Rough conductor with a GGX microfacet distribution. Roughness is the distribution's alpha, and
+// anisotropic roughness runs along dpdu and across it.
+pub struct Metal {
+    fresnel: MetalFresnel,
+    distribution: TrowbridgeReitz,
+    thin_film: Option<ThinFilm>,
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray)> {
-        let reflected = reflect(&ray_in.direction().normalize(), &hit_record.normal);
-        let scattered = Ray::new_at_time(
-            hit_record.point,
-            reflected + random_in_unit_sphere().scalar_mul(self.fuzz),
-            ray_in.time()
-        );
-        let attenuation = self.albedo;
-        Some((attenuation, scattered))
+    fn eval(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if self.distribution.effectively_smooth() {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let frame = hit_record.shading_frame().facing(wo);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let (cos_theta_o, cos_theta_i) = (wo.z(), wi.z());
+        if cos_theta_o <= 0.0 || cos_theta_i <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let wm = wo + wi;
+        if wm.squared_length() == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let wm = wm.normalize();
+        let fresnel = self.reflectance(hit_record, wo.dot(&wm).abs());
+        let d = self.distribution.d(&wm);
+        let g = self.distribution.g(&wo, &wi);
+        fresnel.scalar_mul(d * g / (4.0 * cos_theta_o))
     }
+
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let u = sampler.get_2d();
+        let frame = hit_record.shading_frame().facing(wo);
+        let wo_local = frame.to_local(wo);
+        if wo_local.z() <= 0.0 {
+            return None;
+        }
+        if self.distribution.effectively_smooth() {
+            let wi = Vec3::new(-wo_local.x(), -wo_local.y(), wo_local.z());
+            return Some(BsdfSample {
+                wi: frame.local(&wi),
+                f: self.reflectance(hit_record, wi.z()),
+                pdf: 1.0,
+                is_specular: true,
+            });
+        }
+
+        let wm = self.distribution.sample_wm(&wo_local, u);
+        let wi_local = reflect_about(&wo_local, &wm);
+        if wi_local.z() <= 0.0 {
+            return None;
+        }
+        let wi = frame.local(&wi_local);
+        let pdf = self.pdf(hit_record, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(hit_record, wo, &wi),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> f32 {
+        if self.distribution.effectively_smooth() {
+            return 0.0;
+        }
+        let frame = hit_record.shading_frame().facing(wo);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wm = wo + wi;
+        if wm.squared_length() == 0.0 {
+            return 0.0;
+        }
+        let wm = wm.normalize();
+        self.distribution.visible_d(&wo, &wm) / (4.0 * wo.dot(&wm).abs())
+    }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.effectively_smooth()
+    }
+
 }
 
 impl Metal {
-    pub fn new(albedo: Vec3, fuzz: f32) -> Self {
-        let fuzz = fuzz.min(1.0).max(0.0);
-        Metal { albedo, fuzz }
+    fn reflectance(&self, hit_record: &HitRecord, cos_theta: f32) -> Vec3 {
+        match &self.thin_film {
+            Some(film) => {
+                let (eta, k) = self.fresnel.eta_k();
+                film.reflectance(hit_record, cos_theta, 1.0, eta, k)
+            }
+            None => hit_record.color.upsample_albedo(self.fresnel.evaluate(cos_theta)),
+        }
+    }
+
+    pub fn with_thin_film(mut self, thin_film: ThinFilm) -> Self {
+        self.thin_film = Some(thin_film);
+        self
+    }
+
+    // Tinted metal using Schlick's approximation with albedo as the reflectance at normal incidence
+    pub fn new(albedo: Vec3, roughness: f32) -> Self {
+        Self::anisotropic(albedo, roughness, roughness)
+    }
+
+    // Tinted metal whose roughness differs along dpdu and across it
+    pub fn anisotropic(albedo: Vec3, roughness_u: f32, roughness_v: f32) -> Self {
+        Metal {
+            fresnel: MetalFresnel::Schlick(albedo),
+            distribution: Self::distribution(roughness_u, roughness_v),
+            thin_film: None,
+        }
+    }
+
+    // Conductor with the complex index of refraction eta + i k
+    pub fn conductor(eta: Vec3, k: Vec3, roughness_u: f32, roughness_v: f32) -> Self {
+        Metal {
+            fresnel: MetalFresnel::Conductor { eta, k },
+            distribution: Self::distribution(roughness_u, roughness_v),
+            thin_film: None,
+        }
+    }
+
+    // GGX alpha along each tangent, limited to [0, 1] by every constructor
+    fn distribution(roughness_u: f32, roughness_v: f32) -> TrowbridgeReitz {
+        TrowbridgeReitz::new(roughness_u.clamp(0.0, 1.0), roughness_v.clamp(0.0, 1.0))
     }
 }
 
+// How a dielectric's index of refraction varies with wavelength, in nanometres. The dispersion formulas
+// take wavelengths in micrometres, as their published coefficients do.
+#[derive(Clone, Copy, Debug)]
+pub enum RefractiveIndex {
+    Constant(f32),
+    Cauchy { a: f32, b: f32 },
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl RefractiveIndex {
+    // Wavelength of the sodium D line, which indices are conventionally quoted at
+    const REFERENCE_WAVELENGTH: f32 = 589.3;
+
+    // Index at the given wavelength, or at the reference wavelength when rendering in RGB
+    pub fn at(&self, wavelength: Option<f32>) -> f32 {
+        let lambda = wavelength.unwrap_or(Self::REFERENCE_WAVELENGTH) / 1000.0;
+        match *self {
+            RefractiveIndex::Constant(eta) => eta,
+            RefractiveIndex::Cauchy { a, b } => a + b / (lambda * lambda),
+            RefractiveIndex::Sellmeier { b, c } => {
+                let lambda2 = lambda * lambda;
+                let sum: f32 = b.iter().zip(c.iter()).map(|(b, c)| b * lambda2 / (lambda2 - c)).sum();
+                (1.0 + sum).max(1.0).sqrt()
+            }
+        }
+    }
+
+    fn is_constant(&self) -> bool {
+        matches!(self, RefractiveIndex::Constant(_))
+    }
+}
+
+// Glass-like interface between the outside, which the normal points into, and a medium with the given
+// index of refraction. Smooth by default, and with roughness a GGX microfacet model of both reflection
+// and transmission after Walter et al. 2007, like frosted glass. The medium inside is clear unless given
+// an absorption coefficient, tinting light by Beer-Lambert's law over the distance it travels inside.
 pub struct Dielectric {
-    refractive_index: f32,
+    refractive_index: RefractiveIndex,
+    distribution: TrowbridgeReitz,
+    // Per unit distance, for each of red, green and blue
+    absorption: Vec3,
+    thin_film: Option<ThinFilm>,
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray)> {
-        let attenuation = Vec3::new(1.0, 1.0, 1.0);
-        let (outward_normal, ni_over_nt, cosine) =
-            if ray_in.direction().dot(&hit_record.normal) > 0.0 {
-                (
-                    -hit_record.normal,
-                    self.refractive_index,
-                    self.refractive_index * ray_in.direction().dot(&hit_record.normal)
-                        / ray_in.direction().length(),
-                )
-            } else {
-                (
-                    hit_record.normal,
-                    1.0 / self.refractive_index,
-                    -ray_in.direction().dot(&hit_record.normal) / ray_in.direction().length(),
-                )
-            };
-
-        let reflected = reflect(&ray_in.direction(), &hit_record.normal);
-        let refracted = refract(&ray_in.direction(), &outward_normal, ni_over_nt);
-        let reflect_prob = if refracted.is_some() {
-            schlick(cosine, self.refractive_index)
+    fn eval(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if self.distribution.effectively_smooth() {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let frame = hit_record.shading_frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let eta = self.refractive_index.at(hit_record.color.wavelength());
+        let (wm, etap) = match half_vector(&wo, &wi, eta) {
+            Some(half_vector) => half_vector,
+            None => return Vec3::new(0.0, 0.0, 0.0),
+        };
+        let fresnel = self.reflectance(hit_record, wo.dot(&wm), eta);
+        let d = self.distribution.d(&wm);
+        let g = self.distribution.g(&wo, &wi);
+        if wo.z() * wi.z() > 0.0 {
+            fresnel.scalar_mul(d * g / (4.0 * wo.z()).abs())
         } else {
-            1.0
+            // Radiance is compressed into a smaller solid angle entering a denser medium
+            let jacobian = transmission_jacobian(&wo, &wi, &wm, etap);
+            (Vec3::new(1.0, 1.0, 1.0) - fresnel)
+                .scalar_mul(d * g * (wo.dot(&wm) / wo.z()).abs() * jacobian / (etap * etap))
+        }
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let frame = hit_record.shading_frame();
+        let wo_local = frame.to_local(wo);
+        if wo_local.z() == 0.0 {
+            return None;
+        }
+        let eta = self.refractive_index.at(hit_record.color.wavelength());
+
+        if self.distribution.effectively_smooth() {
+            // Reflection and refraction are delta lobes picked in proportion to the Fresnel term, which then
+            // cancels out of the throughput unless a thin film colors it
+            let reflectance = self.reflectance(hit_record, wo_local.z(), eta);
+            let probability = mean(&reflectance);
+            if uc < probability {
+                let wi = Vec3::new(-wo_local.x(), -wo_local.y(), wo_local.z());
+                return Some(BsdfSample {
+                    wi: frame.local(&wi),
+                    f: reflectance,
+                    pdf: probability,
+                    is_specular: true,
+                });
+            }
+            let (wi, etap) = refract_about(&wo_local, &Vec3::new(0.0, 0.0, 1.0), eta)?;
+            return Some(BsdfSample {
+                wi: frame.local(&wi),
+                f: (Vec3::new(1.0, 1.0, 1.0) - reflectance).scalar_mul(1.0 / (etap * etap)),
+                pdf: 1.0 - probability,
+                is_specular: true,
+            });
+        }
+
+        let wm = self.distribution.sample_wm(&wo_local, u);
+        let reflectance = mean(&self.reflectance(hit_record, wo_local.dot(&wm), eta));
+        let wi_local = if uc < reflectance {
+            let wi = reflect_about(&wo_local, &wm);
+            if wi.z() * wo_local.z() <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let (wi, _) = refract_about(&wo_local, &wm, eta)?;
+            if wi.z() * wo_local.z() >= 0.0 {
+                return None;
+            }
+            wi
         };
+        let wi = frame.local(&wi_local);
+        let pdf = self.pdf(hit_record, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(hit_record, wo, &wi),
+            pdf,
+            is_specular: false,
+        })
+    }
 
-        if rand::thread_rng().gen::<f32>() < reflect_prob {
-            Some((attenuation, Ray::new_at_time(hit_record.point, reflected, ray_in.time())))
+    fn pdf(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> f32 {
+        if self.distribution.effectively_smooth() {
+            return 0.0;
+        }
+        let frame = hit_record.shading_frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let eta = self.refractive_index.at(hit_record.color.wavelength());
+        let (wm, etap) = match half_vector(&wo, &wi, eta) {
+            Some(half_vector) => half_vector,
+            None => return 0.0,
+        };
+        let reflectance = mean(&self.reflectance(hit_record, wo.dot(&wm), eta));
+        let visible = self.distribution.visible_d(&wo, &wm);
+        if wo.z() * wi.z() > 0.0 {
+            visible / (4.0 * wo.dot(&wm).abs()) * reflectance
         } else {
-            Some((attenuation, Ray::new_at_time(hit_record.point, refracted.unwrap(), ray_in.time())))
+            visible * transmission_jacobian(&wo, &wi, &wm, etap) * (1.0 - reflectance)
         }
     }
+
+    fn is_specular(&self) -> bool {
+        self.distribution.effectively_smooth()
+    }
+
+    fn transmittance(&self, hit_record: &HitRecord, distance: f32) -> Vec3 {
+        if hit_record.front_face {
+            return Vec3::new(1.0, 1.0, 1.0);
+        }
+        let absorbed = self.absorption.scalar_mul(-distance);
+        Vec3::new(absorbed.x().exp(), absorbed.y().exp(), absorbed.z().exp())
+    }
+
+    fn is_dispersive(&self) -> bool {
+        !self.refractive_index.is_constant()
+    }
 }
 
 impl Dielectric {
+    // Fresnel reflectance for light arriving from the side cos_theta_i is measured on, where negative is
+    // inside. A thin film lies on the outside of the surface.
+    fn reflectance(&self, hit_record: &HitRecord, cos_theta_i: f32, eta: f32) -> Vec3 {
+        let gray = |value: f32| Vec3::new(value, value, value);
+        match &self.thin_film {
+            None => gray(fresnel_dielectric(cos_theta_i, eta)),
+            Some(film) if cos_theta_i >= 0.0 => film.reflectance(hit_record, cos_theta_i, 1.0, gray(eta), gray(0.0)),
+            Some(film) => film.reflectance(hit_record, -cos_theta_i, eta, gray(1.0), gray(0.0)),
+        }
+    }
+
     pub fn new(refractive_index: f32) -> Self {
-        Dielectric { refractive_index }
+        Self::with_index(RefractiveIndex::Constant(refractive_index))
+    }
+
+    // A dispersive index only splits light into colors when rendering spectrally
+    pub fn with_index(refractive_index: RefractiveIndex) -> Self {
+        Dielectric {
+            refractive_index,
+            distribution: TrowbridgeReitz::new(0.0, 0.0),
+            absorption: Vec3::new(0.0, 0.0, 0.0),
+            thin_film: None,
+        }
+    }
+
+    pub fn with_thin_film(mut self, thin_film: ThinFilm) -> Self {
+        self.thin_film = Some(thin_film);
+        self
+    }
+
+    pub fn with_absorption(mut self, absorption: Vec3) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    // GGX alpha along dpdu and across it
+    pub fn with_roughness(mut self, roughness_u: f32, roughness_v: f32) -> Self {
+        self.distribution = TrowbridgeReitz::new(roughness_u, roughness_v);
+        self
+    }
+}
+
+// Emits light from the front of the surface, or both sides when two sided, and absorbs everything that
+// hits it
+pub struct DiffuseLight {
+    emit: Vec3,
+    two_sided: bool,
+}
+
+impl Material for DiffuseLight {
+    fn sample(&self, _hit_record: &HitRecord, _wo: &Vec3, _sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, hit_record: &HitRecord, _wo: &Vec3) -> Vec3 {
+        if self.two_sided || hit_record.front_face {
+            self.emit
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Vec3) -> Self {
+        DiffuseLight { emit, two_sided: false }
+    }
+
+    pub fn with_two_sided(mut self, two_sided: bool) -> Self {
+        self.two_sided = two_sided;
+        self
     }
 }
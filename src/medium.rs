@@ -0,0 +1,86 @@
+// Participating media filling the inside of closed surfaces, which light crosses in a random walk of
+// scattering events rather than in straight lines
+use crate::{sampler::Sampler, spectrum::PathColor, utils::uniform_sample_sphere, vec3::Vec3};
+
+// Homogeneous medium that scatters isotropically. Coefficients are per unit distance, for each of red,
+// green and blue.
+#[derive(Clone, Copy, Debug)]
+pub struct Medium {
+    scattering: Vec3,
+    extinction: Vec3,
+}
+
+// What happened to light travelling through a medium towards a surface. The weights are transmittance
+// or scattering divided by the density of the event, in whatever the path's color carries.
+pub enum MediumSample {
+    Scattered { distance: f32, weight: Vec3 },
+    Passed { weight: Vec3 },
+}
+
+impl Medium {
+    pub fn new(scattering: Vec3, absorption: Vec3) -> Self {
+        Medium {
+            scattering,
+            extinction: scattering + absorption,
+        }
+    }
+
+    // Medium under a surface that, deep enough, looks like it has the given diffuse albedo, with light
+    // typically travelling mean_free_path inside before leaving. Uses the fit of Chiang et al. 2016,
+    // "Practical and Controllable Subsurface Scattering for Production Path Tracing".
+    pub fn subsurface(albedo: Vec3, mean_free_path: Vec3) -> Self {
+        let coefficients = |albedo: f32, mean_free_path: f32| {
+            let albedo = albedo.clamp(0.0, 0.999);
+            let single_scattering = 1.0 - (albedo * (-5.09406 + albedo * (2.61188 - albedo * 4.31805))).exp();
+            let s = 1.9 - albedo + 3.5 * (albedo - 0.8) * (albedo - 0.8);
+            let extinction = 1.0 / (mean_free_path.max(1e-6) * s);
+            (single_scattering * extinction, extinction)
+        };
+        let (r, g, b) = (
+            coefficients(albedo.r(), mean_free_path.r()),
+            coefficients(albedo.g(), mean_free_path.g()),
+            coefficients(albedo.b(), mean_free_path.b()),
+        );
+        let scattering = Vec3::new(r.0, g.0, b.0);
+        Medium::new(scattering, Vec3::new(r.1, g.1, b.1) - scattering)
+    }
+
+    // Picks where a ray travelling distance through the medium first scatters, if it does. Distances are
+    // sampled for one channel chosen at random and weighted by the mean density over all of them, so
+    // media whose extinction differs between channels stay unbiased in every channel.
+    pub fn sample(&self, distance: f32, color: &PathColor, sampler: &mut dyn Sampler) -> MediumSample {
+        let channel = sampler.get_1d();
+        let u = sampler.get_1d();
+        let extinction = color.upsample(self.extinction);
+        let sigma_t = [extinction.x(), extinction.y(), extinction.z()];
+        let chosen = sigma_t[((channel * 3.0) as usize).min(2)];
+        let transmittance = |t: f32| {
+            Vec3::new((-sigma_t[0] * t).exp(), (-sigma_t[1] * t).exp(), (-sigma_t[2] * t).exp())
+        };
+        // Dividing by the mean density, with a zero density giving a zero weight
+        let weighted = |value: Vec3, density: Vec3| {
+            let pdf = (density.x() + density.y() + density.z()) / 3.0;
+            if pdf > 0.0 {
+                value.scalar_mul(1.0 / pdf)
+            } else {
+                Vec3::new(0.0, 0.0, 0.0)
+            }
+        };
+
+        let t = if chosen > 0.0 { -(1.0 - u).ln() / chosen } else { f32::INFINITY };
+        if t < distance {
+            let transmittance = transmittance(t);
+            let weight = weighted(color.upsample(self.scattering) * transmittance, extinction * transmittance);
+            return MediumSample::Scattered { distance: t, weight };
+        }
+        let transmittance = transmittance(distance);
+        MediumSample::Passed {
+            weight: weighted(transmittance, transmittance),
+        }
+    }
+
+    // New direction after scattering, which is perfectly importance sampled so the path's weight is unchanged
+    pub fn sample_direction(&self, u: (f32, f32)) -> Vec3 {
+        uniform_sample_sphere(u)
+    }
+}
@@ -0,0 +1,299 @@
+// Microfacet building blocks shared by the rough materials. Directions are in a local shading frame with
+// the surface normal along z. Follows the formulation in pbrt-v4.
+use crate::{utils::sample_unit_disk, vec3::Vec3};
+use std::f32::consts::PI;
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals, with separate roughness along the x and y
+// axes of the shading frame
+#[derive(Clone, Copy, Debug)]
+pub struct TrowbridgeReitz {
+    alpha_x: f32,
+    alpha_y: f32,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        TrowbridgeReitz {
+            alpha_x: alpha_x.max(0.0),
+            alpha_y: alpha_y.max(0.0),
+        }
+    }
+
+    // Close enough to a perfect mirror that it has to be treated as a delta lobe
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    // Density of microfacet normals wm, per unit projected area
+    pub fn d(&self, wm: &Vec3) -> f32 {
+        let cos2_theta = wm.z() * wm.z();
+        let sin2_theta = (1.0 - cos2_theta).max(0.0);
+        let tan2_theta = sin2_theta / cos2_theta;
+        if tan2_theta.is_infinite() || tan2_theta.is_nan() {
+            return 0.0;
+        }
+        let cos4_theta = cos2_theta * cos2_theta;
+        if cos4_theta < 1e-16 {
+            return 0.0;
+        }
+        let (cos_phi, sin_phi) = cos_sin_phi(wm);
+        let e = tan2_theta * ((cos_phi / self.alpha_x).powi(2) + (sin_phi / self.alpha_y).powi(2));
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos4_theta * (1.0 + e).powi(2))
+    }
+
+    // Smith's auxiliary function, the ratio of hidden to visible microfacet area seen from w
+    fn lambda(&self, w: &Vec3) -> f32 {
+        let cos2_theta = w.z() * w.z();
+        let tan2_theta = (1.0 - cos2_theta).max(0.0) / cos2_theta;
+        if tan2_theta.is_infinite() || tan2_theta.is_nan() {
+            return 0.0;
+        }
+        let (cos_phi, sin_phi) = cos_sin_phi(w);
+        let alpha2 = (cos_phi * self.alpha_x).powi(2) + (sin_phi * self.alpha_y).powi(2);
+        ((1.0 + alpha2 * tan2_theta).sqrt() - 1.0) / 2.0
+    }
+
+    // Fraction of microfacets visible from w
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction visible from both wo and wi, using the height correlated form
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Density of the visible normals seen from w
+    pub fn visible_d(&self, w: &Vec3, wm: &Vec3) -> f32 {
+        self.g1(w) / w.z().abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    // Samples a microfacet normal visible from w, with density visible_d
+    pub fn sample_wm(&self, w: &Vec3, u: (f32, f32)) -> Vec3 {
+        // Stretch to the configuration where the distribution is the hemisphere
+        let mut wh = Vec3::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()).normalize();
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z() < 0.99999 {
+            Vec3::new(0.0, 0.0, 1.0).cross(&wh).normalize()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // Uniform disk point warped to the projection of the visible hemisphere
+        let p = sample_unit_disk(u);
+        let h = (1.0 - p.x() * p.x()).sqrt();
+        let s = (1.0 + wh.z()) / 2.0;
+        let py = (1.0 - s) * h + s * p.y();
+        let pz = (1.0 - p.x() * p.x() - py * py).max(0.0).sqrt();
+        let nh = t1.scalar_mul(p.x()) + t2.scalar_mul(py) + wh.scalar_mul(pz);
+        Vec3::new(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(1e-6)).normalize()
+    }
+}
+
+fn cos_sin_phi(w: &Vec3) -> (f32, f32) {
+    let sin_theta = (1.0 - w.z() * w.z()).max(0.0).sqrt();
+    if sin_theta == 0.0 {
+        (1.0, 0.0)
+    } else {
+        ((w.x() / sin_theta).clamp(-1.0, 1.0), (w.y() / sin_theta).clamp(-1.0, 1.0))
+    }
+}
+
+// Mirror image of wo about the normal n
+pub fn reflect_about(wo: &Vec3, n: &Vec3) -> Vec3 {
+    -*wo + n.scalar_mul(2.0 * wo.dot(n))
+}
+
+// Direction wo refracts into through a surface with normal n, where eta is the ratio of the index of
+// refraction on the other side of n to the one on its side. Returns the direction with the relative
+// index actually crossed, or None on total internal reflection.
+pub fn refract_about(wo: &Vec3, n: &Vec3, eta: f32) -> Option<(Vec3, f32)> {
+    let (mut n, mut eta, mut cos_theta_i) = (*n, eta, n.dot(wo));
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+        n = -n;
+    }
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(((-*wo).scalar_mul(1.0 / eta) + n.scalar_mul(cos_theta_i / eta - cos_theta_t), eta))
+}
+
+// Microfacet normal halfway between wo and wi, for either reflection or refraction through an interface
+// with relative index eta, along with the relative index actually crossed to get from one to the other.
+// None for directions the model can't connect, including those needing a microfacet facing away from
+// either direction.
+pub fn half_vector(wo: &Vec3, wi: &Vec3, eta: f32) -> Option<(Vec3, f32)> {
+    let (cos_theta_o, cos_theta_i) = (wo.z(), wi.z());
+    if cos_theta_o == 0.0 || cos_theta_i == 0.0 {
+        return None;
+    }
+    let reflect = cos_theta_i * cos_theta_o > 0.0;
+    let etap = match (reflect, cos_theta_o > 0.0) {
+        (true, _) => 1.0,
+        (false, true) => eta,
+        (false, false) => 1.0 / eta,
+    };
+    let wm = wi.scalar_mul(etap) + *wo;
+    if wm.squared_length() == 0.0 {
+        return None;
+    }
+    let wm = wm.normalize();
+    let wm = if wm.z() < 0.0 { -wm } else { wm };
+    if wm.dot(wi) * cos_theta_i < 0.0 || wm.dot(wo) * cos_theta_o < 0.0 {
+        return None;
+    }
+    Some((wm, etap))
+}
+
+// Change of variables from the density of microfacet normals to that of transmitted directions
+pub fn transmission_jacobian(wo: &Vec3, wi: &Vec3, wm: &Vec3, etap: f32) -> f32 {
+    let denominator = (wi.dot(wm) + wo.dot(wm) / etap).powi(2);
+    wi.dot(wm).abs() / denominator
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface, with eta as for refract_about and the
+// incident direction below the surface when cos_theta_i is negative
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let (mut cos_theta_i, mut eta) = (cos_theta_i.clamp(-1.0, 1.0), eta);
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+    }
+    let sin2_theta_i = 1.0 - cos_theta_i * cos_theta_i;
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).max(0.0).sqrt();
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Self {
+        Complex { re, im }
+    }
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+
+    fn div(self, other: Complex) -> Complex {
+        let scale = 1.0 / (other.re * other.re + other.im * other.im);
+        Complex::new(
+            scale * (self.re * other.re + self.im * other.im),
+            scale * (self.im * other.re - self.re * other.im),
+        )
+    }
+
+    // Squared magnitude
+    fn norm(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    // e^(i self), for a phase that may have an imaginary part when the wave decays
+    fn exp_i(self) -> Complex {
+        let magnitude = (-self.im).exp();
+        Complex::new(magnitude * self.re.cos(), magnitude * self.re.sin())
+    }
+
+    fn sqrt(self) -> Complex {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Complex::new(0.0, 0.0);
+        }
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Complex::new(t1, t2)
+        } else {
+            Complex::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+}
+
+// Unpolarized Fresnel reflectance of a conductor with complex index of refraction eta + i k, for one
+// wavelength
+fn fresnel_complex(cos_theta_i: f32, eta: Complex) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_theta_i = Complex::new(1.0 - cos_theta_i * cos_theta_i, 0.0);
+    let sin2_theta_t = sin2_theta_i.div(eta.mul(eta));
+    let cos_theta_t = Complex::new(1.0, 0.0).sub(sin2_theta_t).sqrt();
+    let cos_i = Complex::new(cos_theta_i, 0.0);
+
+    let r_parallel = eta.mul(cos_i).sub(cos_theta_t).div(eta.mul(cos_i).add(cos_theta_t));
+    let r_perpendicular = cos_i.sub(eta.mul(cos_theta_t)).div(cos_i.add(eta.mul(cos_theta_t)));
+    (r_parallel.norm() + r_perpendicular.norm()) / 2.0
+}
+
+// fresnel_complex for each of the red, green and blue indices
+pub fn fresnel_complex_rgb(cos_theta_i: f32, eta: &Vec3, k: &Vec3) -> Vec3 {
+    Vec3::new(
+        fresnel_complex(cos_theta_i, Complex::new(eta.r(), k.r())),
+        fresnel_complex(cos_theta_i, Complex::new(eta.g(), k.g())),
+        fresnel_complex(cos_theta_i, Complex::new(eta.b(), k.b())),
+    )
+}
+
+// Unpolarized reflectance of a film of index film_eta and thickness in nanometres, lying between an incident
+// medium of index incident_eta and a substrate with complex index substrate_eta + i substrate_k, at one
+// wavelength in nanometres. Reflections off the film's two sides interfere, summed as in Airy's formula.
+pub fn fresnel_thin_film(
+    cos_theta_i: f32,
+    incident_eta: f32,
+    film_eta: f32,
+    thickness: f32,
+    substrate_eta: f32,
+    substrate_k: f32,
+    wavelength: f32,
+) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let n1 = Complex::new(incident_eta, 0.0);
+    let n2 = Complex::new(film_eta, 0.0);
+    let n3 = Complex::new(substrate_eta, substrate_k);
+    let cos1 = Complex::new(cos_theta_i, 0.0);
+    // Snell's law gives the cosine of the angle inside each layer, complex past total internal reflection
+    let sin2_theta_i = Complex::new(1.0 - cos_theta_i * cos_theta_i, 0.0);
+    let cos_in = |n: Complex| Complex::new(1.0, 0.0).sub(sin2_theta_i.mul(n1.mul(n1)).div(n.mul(n))).sqrt();
+    let (cos2, cos3) = (cos_in(n2), cos_in(n3));
+
+    let r_perpendicular =
+        |na: Complex, ca: Complex, nb: Complex, cb: Complex| na.mul(ca).sub(nb.mul(cb)).div(na.mul(ca).add(nb.mul(cb)));
+    let r_parallel =
+        |na: Complex, ca: Complex, nb: Complex, cb: Complex| nb.mul(ca).sub(na.mul(cb)).div(nb.mul(ca).add(na.mul(cb)));
+    // Extra phase picked up by a round trip through the film
+    let phase = n2.mul(cos2).mul(Complex::new(4.0 * std::f32::consts::PI * thickness / wavelength, 0.0)).exp_i();
+    let airy = |r12: Complex, r23: Complex| {
+        let r23 = r23.mul(phase);
+        r12.add(r23).div(Complex::new(1.0, 0.0).add(r12.mul(r23))).norm()
+    };
+    let perpendicular = airy(r_perpendicular(n1, cos1, n2, cos2), r_perpendicular(n2, cos2, n3, cos3));
+    let parallel = airy(r_parallel(n1, cos1, n2, cos2), r_parallel(n2, cos2, n3, cos3));
+    ((perpendicular + parallel) / 2.0).clamp(0.0, 1.0)
+}
@@ -1,25 +1,142 @@
-use crate::{bounding_box::BoundingBox, material::Material, ray::Ray, vec3::Vec3};
+use crate::{
+    bounding_box::BoundingBox, light::Light, material::Material, onb::Onb, ray::Ray, rng::hash, spectrum::PathColor,
+    texture::Texture, vec3::Vec3,
+};
+use std::sync::Arc;
 
+#[derive(Clone, Copy)]
 pub struct HitRecord<'a> {
     pub t: f32,
     pub point: Vec3,
+    // Normal that materials shade with, which normal and bump maps tilt away from the surface's
     pub normal: Vec3,
+    // The surface's own normal, for telling which side of it a direction is on and offsetting rays
+    pub geometric_normal: Vec3,
+    // Whether the ray arrived from the side the geometric normal points to, rather than from behind
+    pub front_face: bool,
+    // Bound on the absolute rounding error in each coordinate of the point
+    pub error: Vec3,
+    // Surface coordinates for texture lookups, each running over [0, 1] across the primitive
+    pub uv: (f32, f32),
+    // Rates of change of the point with the surface coordinates, zero where a surface doesn't set them
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub material: &'a dyn Material,
+    // The area light this surface belongs to, if it is one of the scene's lights, so integrators can tell which
+    // light a ray hit and weigh its emission against sampling that light directly
+    pub light: Option<&'a dyn Light>,
+    // What the path carries, set by the integrator, which materials give their BSDF values in
+    pub color: PathColor,
 }
 
 impl<'a> HitRecord<'a> {
-    pub fn new(t: f32, point: Vec3, normal: Vec3, material: &'a dyn Material) -> Self {
+    pub fn new(ray: &Ray, t: f32, point: Vec3, normal: Vec3, material: &'a dyn Material) -> Self {
         HitRecord {
             t,
             point,
             normal,
+            geometric_normal: normal,
+            front_face: ray.direction().dot(&normal) < 0.0,
+            error: Vec3::new(0.0, 0.0, 0.0),
+            uv: (0.0, 0.0),
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
             material,
+            light: None,
+            color: PathColor::Rgb,
         }
     }
+
+    // Frame around the shading normal with u along dpdu, shared by normal maps and anisotropic materials
+    pub fn shading_frame(&self) -> Onb {
+        Onb::build_from_w_and_tangent(&self.normal, &self.dpdu)
+    }
+
+    // Origin for rays leaving the surface towards direction. The point is pushed along the geometric
+    // normal just past its error bound, then rounded further away, so the ray can't start on the wrong
+    // side of the surface and hit it again however large the scene's coordinates are.
+    pub fn offset_origin(&self, direction: &Vec3) -> Vec3 {
+        let normal = self.geometric_normal;
+        let distance = normal.abs().dot(&self.error);
+        let offset = if direction.dot(&normal) < 0.0 {
+            normal.scalar_mul(-distance)
+        } else {
+            normal.scalar_mul(distance)
+        };
+        let origin = self.point + offset;
+        let round_away = |value: f32, offset: f32| {
+            if offset > 0.0 {
+                value.next_up()
+            } else if offset < 0.0 {
+                value.next_down()
+            } else {
+                value
+            }
+        };
+        Vec3::new(
+            round_away(origin.x(), offset.x()),
+            round_away(origin.y(), offset.y()),
+            round_away(origin.z(), offset.z()),
+        )
+    }
+
+    pub fn spawn_ray(&self, direction: &Vec3, time: f32) -> Ray {
+        Ray::new_at_time(self.offset_origin(direction), *direction, time)
+    }
+}
+
+// Opacity texture cutting a surface's outline out of a simpler shape, like a leaf drawn on a quad. Rays
+// pass through where it is zero and, where it is partly opaque, that fraction of them stop. Which ones is
+// decided by hashing the ray and the hit, so a given ray always sees the same surface.
+#[derive(Clone)]
+pub struct AlphaMask {
+    opacity: Arc<dyn Texture>,
+}
+
+impl AlphaMask {
+    pub fn new(opacity: Arc<dyn Texture>) -> Self {
+        AlphaMask { opacity }
+    }
+
+    pub fn passes_through(&self, ray: &Ray, hit_record: &HitRecord) -> bool {
+        let opacity = self.opacity.scalar(hit_record);
+        if opacity >= 1.0 {
+            return false;
+        }
+        if opacity <= 0.0 {
+            return true;
+        }
+        let (origin, direction) = (ray.origin(), ray.direction());
+        let bits = [
+            origin.x(),
+            origin.y(),
+            origin.z(),
+            direction.x(),
+            direction.y(),
+            direction.z(),
+            hit_record.t,
+        ]
+        .map(|value| value.to_bits() as u64);
+        let u = (hash(&bits) >> 40) as f32 / (1u64 << 24) as f32;
+        u >= opacity
+    }
+}
+
+// The hit, unless an alpha mask lets the ray through there. Area lights are never cut out, as their light
+// samples cover the whole surface.
+pub fn unless_masked<'a>(
+    alpha_mask: Option<&AlphaMask>,
+    ray: &Ray,
+    hit_record: HitRecord<'a>,
+) -> Option<HitRecord<'a>> {
+    match alpha_mask {
+        Some(alpha_mask) if hit_record.light.is_none() && alpha_mask.passes_through(ray, &hit_record) => None,
+        _ => Some(hit_record),
+    }
 }
 
 pub trait Object: Sync {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
 
     fn bounding_box(&self) -> Option<&BoundingBox>;
 }
@@ -0,0 +1,54 @@
+use crate::vec3::Vec3;
+
+// Orthonormal basis with w along a given unit vector, for moving directions between world space and a
+// local frame around a surface normal
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    // Branchless construction from Duff et al. 2017
+    pub fn build_from_w(w: &Vec3) -> Self {
+        let sign = 1.0f32.copysign(w.z());
+        let a = -1.0 / (sign + w.z());
+        let b = w.x() * w.y() * a;
+        let u = Vec3::new(1.0 + sign * w.x() * w.x() * a, sign * b, -sign * w.x());
+        let v = Vec3::new(b, sign + w.y() * w.y() * a, -w.y());
+        Onb { u, v, w: *w }
+    }
+
+    // Basis with u along the part of tangent perpendicular to w, so anisotropic directions follow the
+    // surface. Falls back to arbitrary tangents where there is no such part.
+    pub fn build_from_w_and_tangent(w: &Vec3, tangent: &Vec3) -> Self {
+        let u = *tangent - w.scalar_mul(w.dot(tangent));
+        let length = u.length();
+        if length <= 1e-6 * tangent.length() || !length.is_finite() {
+            return Self::build_from_w(w);
+        }
+        let u = u.scalar_mul(1.0 / length);
+        Onb { u, v: w.cross(&u), w: *w }
+    }
+
+    // The same basis turned to face the side of the surface direction is on, keeping u so anisotropy
+    // doesn't depend on the side
+    pub fn facing(self, direction: &Vec3) -> Self {
+        if self.w.dot(direction) < 0.0 {
+            Onb { u: self.u, v: -self.v, w: -self.w }
+        } else {
+            self
+        }
+    }
+
+    // Coordinates of the world space vector a in this basis
+    #[inline]
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+
+    #[inline]
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        self.u.scalar_mul(a.x()) + self.v.scalar_mul(a.y()) + self.w.scalar_mul(a.z())
+    }
+}
@@ -0,0 +1,367 @@
+// Disney's principled BSDF (Burley, "Physically Based Shading at Disney", 2012, with refraction from the
+// 2015 extension): one material whose artist friendly parameters blend a diffuse base with sheen, a GGX
+// specular lobe, a clearcoat and rough refraction. Every parameter is a texture, with the scalar ones
+// expected to lie in [0, 1] apart from the index of refraction.
+use crate::{
+    material::{BsdfSample, Material},
+    microfacet::{
+        fresnel_dielectric, half_vector, reflect_about, refract_about, transmission_jacobian, TrowbridgeReitz,
+    },
+    object::HitRecord,
+    sampler::Sampler,
+    texture::{Constant, Texture},
+    utils::cosine_sample_hemisphere,
+    vec3::Vec3,
+};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    specular: Arc<dyn Texture>,
+    specular_tint: Arc<dyn Texture>,
+    sheen: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    clearcoat_gloss: Arc<dyn Texture>,
+    transmission: Arc<dyn Texture>,
+    refractive_index: Arc<dyn Texture>,
+}
+
+impl Principled {
+    // A rough dielectric of the given color, with the remaining parameters at Disney's defaults
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        let scalar = |value: f32| Arc::new(Constant::scalar(value)) as Arc<dyn Texture>;
+        Principled {
+            base_color,
+            metallic: scalar(0.0),
+            roughness: scalar(0.5),
+            specular: scalar(0.5),
+            specular_tint: scalar(0.0),
+            sheen: scalar(0.0),
+            clearcoat: scalar(0.0),
+            clearcoat_gloss: scalar(1.0),
+            transmission: scalar(0.0),
+            refractive_index: scalar(1.5),
+        }
+    }
+
+    pub fn with_metallic(mut self, metallic: Arc<dyn Texture>) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    // Squared to give the GGX alpha
+    pub fn with_roughness(mut self, roughness: Arc<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    // Dielectric reflectance at normal incidence, where 0.5 gives 4%
+    pub fn with_specular(mut self, specular: Arc<dyn Texture>) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    // How much the dielectric specular takes on the base color's hue
+    pub fn with_specular_tint(mut self, specular_tint: Arc<dyn Texture>) -> Self {
+        self.specular_tint = specular_tint;
+        self
+    }
+
+    // Extra reflection at grazing angles for cloth
+    pub fn with_sheen(mut self, sheen: Arc<dyn Texture>) -> Self {
+        self.sheen = sheen;
+        self
+    }
+
+    // Strength of a second, colorless specular layer
+    pub fn with_clearcoat(mut self, clearcoat: Arc<dyn Texture>) -> Self {
+        self.clearcoat = clearcoat;
+        self
+    }
+
+    // From a satin clearcoat at 0 to a glossy one at 1
+    pub fn with_clearcoat_gloss(mut self, clearcoat_gloss: Arc<dyn Texture>) -> Self {
+        self.clearcoat_gloss = clearcoat_gloss;
+        self
+    }
+
+    // Fraction of the non metallic base that refracts rather than scattering diffusely
+    pub fn with_transmission(mut self, transmission: Arc<dyn Texture>) -> Self {
+        self.transmission = transmission;
+        self
+    }
+
+    // Index of refraction used for transmission
+    pub fn with_refractive_index(mut self, refractive_index: Arc<dyn Texture>) -> Self {
+        self.refractive_index = refractive_index;
+        self
+    }
+
+    fn parameters(&self, hit_record: &HitRecord) -> Parameters {
+        let unit = |texture: &Arc<dyn Texture>| texture.scalar(hit_record).clamp(0.0, 1.0);
+        let base_color = self.base_color.value(hit_record);
+        let base_color = Vec3::new(base_color.r().max(0.0), base_color.g().max(0.0), base_color.b().max(0.0));
+        let roughness = unit(&self.roughness);
+        let alpha = (roughness * roughness).max(1e-3);
+        Parameters {
+            base_color,
+            metallic: unit(&self.metallic),
+            roughness,
+            specular: unit(&self.specular),
+            specular_tint: unit(&self.specular_tint),
+            sheen: unit(&self.sheen),
+            clearcoat: unit(&self.clearcoat),
+            clearcoat_gloss: unit(&self.clearcoat_gloss),
+            transmission: unit(&self.transmission),
+            eta: self.refractive_index.scalar(hit_record).max(1.0),
+            distribution: TrowbridgeReitz::new(alpha, alpha),
+        }
+    }
+}
+
+// Parameter values at one hit. Directions passed to its methods are in the local frame of the surface
+// normal; the reflection lobes are two sided and evaluated as if wo were above the surface.
+struct Parameters {
+    base_color: Vec3,
+    metallic: f32,
+    roughness: f32,
+    specular: f32,
+    specular_tint: f32,
+    sheen: f32,
+    clearcoat: f32,
+    clearcoat_gloss: f32,
+    transmission: f32,
+    eta: f32,
+    distribution: TrowbridgeReitz,
+}
+
+impl Parameters {
+    // Hue and saturation of the base color at unit luminance
+    fn tint(&self) -> Vec3 {
+        let luminance = self.base_color.luminance();
+        if luminance > 0.0 {
+            self.base_color.scalar_mul(1.0 / luminance)
+        } else {
+            Vec3::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    // Specular color at normal incidence, going from a dielectric's to the base color as metallic rises
+    fn specular_color(&self) -> Vec3 {
+        let dielectric = Vec3::new(1.0, 1.0, 1.0)
+            .lerp(&self.tint(), self.specular_tint)
+            .scalar_mul(0.08 * self.specular);
+        dielectric.lerp(&self.base_color, self.metallic)
+    }
+
+    fn diffuse_weight(&self) -> f32 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    fn transmission_weight(&self) -> f32 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    fn clearcoat_alpha(&self) -> f32 {
+        lerp(0.1, 0.001, self.clearcoat_gloss)
+    }
+
+    // Chance of sampling the diffuse, specular, clearcoat and transmission lobes, roughly in proportion to
+    // how much each reflects towards wo
+    fn lobe_probabilities(&self, wo: &Vec3) -> [f32; 4] {
+        let cos_theta = wo.z().abs();
+        let weights = [
+            self.diffuse_weight(),
+            schlick(self.specular_color(), cos_theta).luminance(),
+            0.25 * self.clearcoat * schlick_scalar(0.04, cos_theta),
+            self.transmission_weight() * (1.0 - fresnel_dielectric(cos_theta, self.eta)),
+        ];
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            return [0.0, 1.0, 0.0, 0.0];
+        }
+        weights.map(|weight| weight / total)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wo.z() == 0.0 || wi.z() == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        if wo.z() * wi.z() > 0.0 {
+            self.eval_reflection(&upper(wo), &upper(wi))
+        } else {
+            self.eval_transmission(wo, wi)
+        }
+    }
+
+    fn eval_reflection(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let wh = (*wo + *wi).normalize();
+        let cos_theta_d = wi.dot(&wh);
+        let (fo, fi) = (schlick_weight(wo.z()), schlick_weight(wi.z()));
+
+        // Diffuse that darkens at grazing angles for smooth surfaces and gains a retro-reflective peak on
+        // rough ones
+        let retro = 2.0 * self.roughness * cos_theta_d * cos_theta_d;
+        let diffuse = self
+            .base_color
+            .scalar_mul(((1.0 - fo / 2.0) * (1.0 - fi / 2.0) + retro * (fo + fi + fo * fi * (retro - 1.0))) / PI);
+        // Sheen is tinted halfway towards the base color, Disney's default
+        let sheen_color = Vec3::new(1.0, 1.0, 1.0).lerp(&self.tint(), 0.5);
+        let sheen = sheen_color.scalar_mul(self.sheen * schlick_weight(cos_theta_d));
+        let mut f = (diffuse + sheen).scalar_mul(self.diffuse_weight());
+
+        let d = self.distribution.d(&wh);
+        let g = self.distribution.g(wo, wi);
+        f += schlick(self.specular_color(), cos_theta_d).scalar_mul(d * g / (4.0 * wo.z() * wi.z()));
+
+        if self.clearcoat > 0.0 {
+            let d = gtr1(wh.z(), self.clearcoat_alpha());
+            let g = TrowbridgeReitz::new(0.25, 0.25).g(wo, wi);
+            let fresnel = schlick_scalar(0.04, cos_theta_d);
+            let value = 0.25 * self.clearcoat * d * g * fresnel / (4.0 * wo.z() * wi.z());
+            f += Vec3::new(value, value, value);
+        }
+        f.scalar_mul(wi.z())
+    }
+
+    fn eval_transmission(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let weight = self.transmission_weight();
+        if weight == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        let (wm, etap) = match half_vector(wo, wi, self.eta) {
+            Some(half_vector) => half_vector,
+            None => return Vec3::new(0.0, 0.0, 0.0),
+        };
+        let fresnel = fresnel_dielectric(wo.dot(&wm), self.eta);
+        let d = self.distribution.d(&wm);
+        let g = self.distribution.g(wo, wi);
+        let jacobian = transmission_jacobian(wo, wi, &wm, etap);
+        let value = d * (1.0 - fresnel) * g * (wo.dot(&wm) / wo.z()).abs() * jacobian / (etap * etap);
+        // Tinted by the square root of the base color, so light passing in and back out takes on its color
+        let tint = Vec3::new(self.base_color.r().sqrt(), self.base_color.g().sqrt(), self.base_color.b().sqrt());
+        tint.scalar_mul(weight * value)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if wo.z() == 0.0 || wi.z() == 0.0 {
+            return 0.0;
+        }
+        let probabilities = self.lobe_probabilities(wo);
+        if wo.z() * wi.z() > 0.0 {
+            let (wo, wi) = (upper(wo), upper(wi));
+            let wh = (wo + wi).normalize();
+            let reflection_jacobian = 1.0 / (4.0 * wo.dot(&wh).abs());
+            probabilities[0] * wi.z() / PI
+                + probabilities[1] * self.distribution.visible_d(&wo, &wh) * reflection_jacobian
+                + probabilities[2] * gtr1(wh.z(), self.clearcoat_alpha()) * wh.z() * reflection_jacobian
+        } else {
+            match half_vector(wo, wi, self.eta) {
+                Some((wm, etap)) => {
+                    probabilities[3]
+                        * self.distribution.visible_d(wo, &wm)
+                        * transmission_jacobian(wo, wi, &wm, etap)
+                }
+                None => 0.0,
+            }
+        }
+    }
+
+    // Direction from one lobe, picked with uc
+    fn sample_wi(&self, wo: &Vec3, uc: f32, u: (f32, f32)) -> Option<Vec3> {
+        let probabilities = self.lobe_probabilities(wo);
+        let mut lobe = 0;
+        let mut cumulative = probabilities[0];
+        while lobe < 3 && uc >= cumulative {
+            lobe += 1;
+            cumulative += probabilities[lobe];
+        }
+        let wo_upper = upper(wo);
+        let flip = |w: Vec3| if wo.z() < 0.0 { Vec3::new(w.x(), w.y(), -w.z()) } else { w };
+        let wi = match lobe {
+            0 => flip(cosine_sample_hemisphere(u)),
+            1 => flip(reflect_about(&wo_upper, &self.distribution.sample_wm(&wo_upper, u))),
+            2 => flip(reflect_about(&wo_upper, &sample_gtr1(self.clearcoat_alpha(), u))),
+            _ => refract_about(wo, &self.distribution.sample_wm(wo, u), self.eta)?.0,
+        };
+        let same_side = wi.z() * wo.z() > 0.0;
+        if same_side == (lobe == 3) {
+            return None;
+        }
+        Some(wi)
+    }
+}
+
+impl Material for Principled {
+    fn eval(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let frame = hit_record.shading_frame();
+        hit_record.color.upsample_bsdf(self.parameters(hit_record).eval(&frame.to_local(wo), &frame.to_local(wi)))
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let frame = hit_record.shading_frame();
+        let parameters = self.parameters(hit_record);
+        let wo_local = frame.to_local(wo);
+        if wo_local.z() == 0.0 {
+            return None;
+        }
+        let wi_local = parameters.sample_wi(&wo_local, uc, u)?;
+        let pdf = parameters.pdf(&wo_local, &wi_local);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi: frame.local(&wi_local),
+            f: hit_record.color.upsample_bsdf(parameters.eval(&wo_local, &wi_local)),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> f32 {
+        let frame = hit_record.shading_frame();
+        self.parameters(hit_record).pdf(&frame.to_local(wo), &frame.to_local(wi))
+    }
+}
+
+// Reflected to the side of the surface the normal points to
+fn upper(w: &Vec3) -> Vec3 {
+    Vec3::new(w.x(), w.y(), w.z().abs())
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn schlick_weight(cos_theta: f32) -> f32 {
+    (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+fn schlick_scalar(r0: f32, cos_theta: f32) -> f32 {
+    lerp(r0, 1.0, schlick_weight(cos_theta))
+}
+
+fn schlick(r0: Vec3, cos_theta: f32) -> Vec3 {
+    r0.lerp(&Vec3::new(1.0, 1.0, 1.0), schlick_weight(cos_theta))
+}
+
+// Generalized Trowbridge-Reitz distribution with exponent one, which has the long tail of the clearcoat
+fn gtr1(cos_theta_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    (alpha2 - 1.0) / (PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta_h * cos_theta_h))
+}
+
+// Half vector distributed as gtr1 times its cosine
+fn sample_gtr1(alpha: f32, u: (f32, f32)) -> Vec3 {
+    let alpha2 = alpha * alpha;
+    let cos_theta = ((1.0 - alpha2.powf(1.0 - u.0)) / (1.0 - alpha2)).max(0.0).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
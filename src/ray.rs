@@ -9,6 +9,7 @@ pub struct Ray {
 
 impl Ray {
 
+    #[allow(dead_code)]
     pub fn new(origin: Vec3, dir: Vec3) -> Self {
         Ray {origin, dir, time: 0.0}
     }
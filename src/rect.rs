@@ -0,0 +1,87 @@
+use crate::{
+    bounding_box::BoundingBox,
+    light::Light,
+    material::Material,
+    object::{unless_masked, AlphaMask, HitRecord, Object},
+    ray::Ray,
+    utils::gamma,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Parallelogram spanned by two edges from a corner. Its front faces along edge1 x edge2.
+pub struct Rect {
+    corner: Vec3,
+    edge1: Vec3,
+    edge2: Vec3,
+    normal: Vec3,
+    // Maps a point in the plane to its coordinates along the two edges
+    w: Vec3,
+    material: Box<dyn Material>,
+    bbox: BoundingBox,
+    light: Option<Arc<dyn Light>>,
+    alpha_mask: Option<AlphaMask>,
+}
+
+impl Rect {
+    pub fn new(corner: Vec3, edge1: Vec3, edge2: Vec3, material: Box<dyn Material>) -> Self {
+        let n = edge1.cross(&edge2);
+        let w = n.scalar_mul(1.0 / n.dot(&n));
+        let bbox = BoundingBox::from_points(&[corner, corner + edge1, corner + edge2, corner + edge1 + edge2]);
+        Rect {
+            corner,
+            edge1,
+            edge2,
+            normal: n.normalize(),
+            w,
+            material,
+            bbox,
+            light: None,
+            alpha_mask: None,
+        }
+    }
+
+    // Marks the rect as the surface of an area light
+    pub fn with_light(mut self, light: Arc<dyn Light>) -> Self {
+        self.light = Some(light);
+        self
+    }
+
+    pub fn with_alpha_mask(mut self, alpha_mask: AlphaMask) -> Self {
+        self.alpha_mask = Some(alpha_mask);
+        self
+    }
+}
+
+impl Object for Rect {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let denominator = self.normal.dot(&ray.direction());
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.corner - ray.origin()).dot(&self.normal) / denominator;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let point = ray.point_at_parameter(t);
+        let planar = point - self.corner;
+        let alpha = self.w.dot(&planar.cross(&self.edge2));
+        let beta = self.w.dot(&self.edge1.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        // Rebuilt from the surface coordinates, which bounds its error independently of how far the ray came
+        let (corner, along1, along2) = (self.corner, self.edge1.scalar_mul(alpha), self.edge2.scalar_mul(beta));
+        let mut hit_record = HitRecord::new(ray, t, corner + along1 + along2, self.normal, self.material.as_ref());
+        hit_record.error = (corner.abs() + along1.abs() + along2.abs()).scalar_mul(gamma(7));
+        hit_record.uv = (alpha, beta);
+        hit_record.dpdu = self.edge1;
+        hit_record.dpdv = self.edge2;
+        hit_record.light = self.light.as_deref();
+        unless_masked(self.alpha_mask.as_ref(), ray, hit_record)
+    }
+
+    fn bounding_box(&self) -> Option<&BoundingBox> {
+        Some(&self.bbox)
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    camera::Camera,
+    film::{Film, FilmTile},
+    filter::FilterKind,
+    integrator::{Integrator, IntegratorKind, PathDepth},
+    sampler::SamplerKind,
+    scene::Scene,
+    spectrum::ColorMode,
+    tile::{generate_tiles, Tile, TileOrder},
+};
+
+use rayon::prelude::*;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub filter: FilterKind,
+    pub integrator: IntegratorKind,
+    pub path_depth: PathDepth,
+    pub color_mode: ColorMode,
+    // When set, pixels stop sampling once the standard error of their displayed value drops below this,
+    // with samples_per_pixel as the cap
+    pub adaptive_threshold: Option<f32>,
+    pub min_samples_per_pixel: usize,
+}
+
+// Running mean and variance of a pixel's luminance (Welford's algorithm)
+#[derive(Default)]
+struct PixelStatistics {
+    count: usize,
+    mean: f32,
+    m2: f32,
+}
+
+impl PixelStatistics {
+    fn add(&mut self, value: f32) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // Output is gamma corrected with a square root, so the error in the displayed value is roughly
+    // the standard error of the mean divided by 2 * sqrt(mean)
+    fn display_error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::MAX;
+        }
+        let variance = self.m2 / (self.count - 1) as f32;
+        let standard_error = (variance / self.count as f32).sqrt();
+        if standard_error == 0.0 {
+            return 0.0;
+        }
+        standard_error / (2.0 * self.mean.max(f32::EPSILON).sqrt())
+    }
+}
+
+fn render_tile(
+    film_tile: &mut FilmTile,
+    tile: &Tile,
+    camera: &Camera,
+    scene: &Scene,
+    integrator: &dyn Integrator,
+    settings: &RenderSettings,
+) {
+    let mut sampler = settings.sampler.create(settings.samples_per_pixel, settings.seed);
+    for (x, y) in tile.pixels() {
+        let mut statistics = PixelStatistics::default();
+        for sample_index in 0..settings.samples_per_pixel {
+            sampler.start_pixel_sample(x, y, sample_index);
+            let (dx, dy) = sampler.get_2d();
+            let (film_x, film_y) = (x as f32 + dx, y as f32 + dy);
+            // Film rows run top down but v runs from the bottom of the image
+            let u = film_x / settings.width as f32;
+            let v = 1.0 - film_y / settings.height as f32;
+            let mut color = settings.color_mode.start_path(sampler.as_mut());
+            let ray = camera.get_ray(u, v, sampler.as_mut());
+            let value = integrator.li(&ray, scene, sampler.as_mut(), &mut color);
+            let sample = color.to_rgb(value);
+            film_tile.add_sample(film_x, film_y, sample);
+            statistics.add(sample.luminance());
+
+            if let Some(threshold) = settings.adaptive_threshold {
+                if statistics.count >= settings.min_samples_per_pixel && statistics.display_error() <= threshold {
+                    break;
+                }
+            }
+        }
+        film_tile.set_sample_count(x, y, statistics.count);
+    }
+}
+
+pub fn render(camera: &Camera, scene: &Scene, settings: &RenderSettings) -> Film {
+    let tiles = generate_tiles(settings.width, settings.height, settings.tile_size, settings.tile_order);
+    let film = Film::new(settings.width, settings.height, settings.filter.create());
+    let integrator = settings.integrator.create(settings.path_depth);
+
+    // Each worker pulls the next tile off a shared counter so tiles are started in the requested order
+    let next_tile = AtomicUsize::new(0);
+    let film_tiles = Mutex::new(Vec::with_capacity(tiles.len()));
+    (0..rayon::current_num_threads()).into_par_iter().for_each(|_| loop {
+        let index = next_tile.fetch_add(1, Ordering::Relaxed);
+        if index >= tiles.len() {
+            break;
+        }
+        let tile = &tiles[index];
+        let mut film_tile = film.tile(tile);
+        render_tile(&mut film_tile, tile, camera, scene, integrator.as_ref(), settings);
+        film_tiles.lock().unwrap().push((index, film_tile));
+    });
+
+    // Padded tiles overlap, so they are merged in a fixed order to keep the result independent of which
+    // thread finished first
+    let mut film_tiles = film_tiles.into_inner().unwrap();
+    film_tiles.sort_by_key(|(index, _)| *index);
+    for (_, film_tile) in film_tiles {
+        film.merge_tile(film_tile);
+    }
+
+    film
+}
@@ -0,0 +1,73 @@
+use rand::{Error, RngCore};
+
+// PCG32 (XSH RR variant). It is cheap to seed, so a fresh generator can be created for every pixel sample
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Pcg32 {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    #[inline]
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+    }
+}
+
+impl RngCore for Pcg32 {
+    #[inline]
+    fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.step();
+        let xor_shifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rotation = (old_state >> 59) as u32;
+        xor_shifted.rotate_right(rotation)
+    }
+
+    #[inline]
+    fn next_u64(&mut self) -> u64 {
+        (u64::from(self.next_u32()) << 32) | u64::from(self.next_u32())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// 64 bit finalizer from MurmurHash3, used to turn structured inputs such as pixel coordinates into seeds
+#[inline]
+pub fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    v ^= v >> 33;
+    v = v.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    v ^= v >> 33;
+    v
+}
+
+#[inline]
+pub fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(0x9e37_79b9_7f4a_7c15, |h, &v| mix_bits(h ^ v.wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
@@ -0,0 +1,358 @@
+use crate::rng::{hash, mix_bits, Pcg32};
+use rand::Rng;
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+// Source of the random numbers used while tracing a single camera sample. Values only depend on the seed,
+// the pixel and the sample index, so renders are reproducible whatever order the tiles are rendered in.
+// Each call to get_1d or get_2d consumes the next dimension of the sample vector.
+pub trait Sampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, sample_index: usize);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn create(self, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+impl std::str::FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("Unknown sampler '{}'", s)),
+        }
+    }
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Pcg32,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        IndependentSampler {
+            seed,
+            rng: Pcg32::new(seed, 0),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, sample_index: usize) {
+        self.rng = Pcg32::new(hash(&[self.seed, x as u64, y as u64, sample_index as u64]), self.seed);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
+
+// Jittered sampling. Every dimension is split into one stratum per sample and each pixel sample gets a
+// different stratum, chosen by a random permutation that differs per pixel and dimension.
+pub struct StratifiedSampler {
+    samples_per_pixel: usize,
+    x_strata: usize,
+    y_strata: usize,
+    seed: u64,
+    pixel: (usize, usize),
+    sample_index: usize,
+    dimension: u64,
+    rng: Pcg32,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> Self {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        // Use the most square grid that has exactly one stratum per sample
+        let x_strata = (1..=(samples_per_pixel as f64).sqrt() as usize)
+            .rev()
+            .find(|d| samples_per_pixel.is_multiple_of(*d))
+            .unwrap_or(1);
+        StratifiedSampler {
+            samples_per_pixel,
+            x_strata,
+            y_strata: samples_per_pixel / x_strata,
+            seed,
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed, 0),
+        }
+    }
+
+    fn stratum(&mut self) -> usize {
+        let permutation_seed = hash(&[self.pixel.0 as u64, self.pixel.1 as u64, self.dimension, self.seed]);
+        self.dimension += 1;
+        // Sample indices past the stratified count, e.g. from adaptive sampling, wrap around
+        let index = self.sample_index % self.samples_per_pixel;
+        permutation_element(index as u32, self.samples_per_pixel as u32, permutation_seed as u32) as usize
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, sample_index: usize) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        self.rng = Pcg32::new(hash(&[self.seed, x as u64, y as u64, sample_index as u64]), self.seed);
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let stratum = self.stratum();
+        let jitter: f32 = self.rng.gen();
+        ((stratum as f32 + jitter) / self.samples_per_pixel as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let stratum = self.stratum();
+        let (sx, sy) = (stratum % self.x_strata, stratum / self.x_strata);
+        let (jx, jy): (f32, f32) = (self.rng.gen(), self.rng.gen());
+        (
+            ((sx as f32 + jx) / self.x_strata as f32).min(ONE_MINUS_EPSILON),
+            ((sy as f32 + jy) / self.y_strata as f32).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+// Halton sequence with a different prime base per dimension, Owen scrambled per pixel so that
+// neighbouring pixels don't share the same sample pattern
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: (usize, usize),
+    sample_index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        HaltonSampler {
+            seed,
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let scramble = hash(&[self.pixel.0 as u64, self.pixel.1 as u64, dimension as u64, self.seed]);
+        if dimension >= PRIMES.len() {
+            // Past the prime table the sequence loses its structure anyway, so fall back to hashing
+            return hash_to_float(mix_bits(scramble ^ self.sample_index));
+        }
+        owen_scrambled_radical_inverse(PRIMES[dimension], self.sample_index, scramble as u32)
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, sample_index: usize) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.next_sample()
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.next_sample(), self.next_sample())
+    }
+}
+
+// Owen scrambled Sobol sequence (Burley 2020). Only the first two Sobol dimensions are used; higher
+// dimensions are padded by shuffling the sample index with a different seed per dimension pair.
+pub struct SobolSampler {
+    seed: u64,
+    pixel: (usize, usize),
+    sample_index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        SobolSampler {
+            seed,
+            pixel: (0, 0),
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn dimension_hash(&mut self) -> u64 {
+        let h = hash(&[self.pixel.0 as u64, self.pixel.1 as u64, self.dimension, self.seed]);
+        self.dimension += 1;
+        h
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, sample_index: usize) {
+        self.pixel = (x, y);
+        self.sample_index = sample_index as u32;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let h = self.dimension_hash();
+        let index = nested_uniform_scramble(self.sample_index, h as u32);
+        bits_to_float(nested_uniform_scramble(sobol(index, 0), (h >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let h = self.dimension_hash();
+        let index = nested_uniform_scramble(self.sample_index, h as u32);
+        let h2 = mix_bits(h);
+        (
+            bits_to_float(nested_uniform_scramble(sobol(index, 0), (h >> 32) as u32)),
+            bits_to_float(nested_uniform_scramble(sobol(index, 1), h2 as u32)),
+        )
+    }
+}
+
+// Generator matrices for the first two Sobol dimensions: the van der Corput sequence and the
+// one generated by the primitive polynomial x + 1
+fn sobol(index: u32, dimension: usize) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction = if dimension == 0 {
+            direction >> 1
+        } else {
+            direction ^ (direction >> 1)
+        };
+    }
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+#[inline]
+fn bits_to_float(bits: u32) -> f32 {
+    ((bits >> 8) as f32 / (1u32 << 24) as f32).min(ONE_MINUS_EPSILON)
+}
+
+#[inline]
+fn hash_to_float(h: u64) -> f32 {
+    bits_to_float((h >> 32) as u32)
+}
+
+fn owen_scrambled_radical_inverse(base: u32, mut index: u64, hash: u32) -> f32 {
+    let inv_base = 1.0 / f64::from(base);
+    // Enough digits to cover the precision of an f32 even once index has run out of non zero digits
+    let digits = (24.0 / f64::from(base).log2()).ceil() as usize + 1;
+    let mut reversed_digits: u64 = 0;
+    let mut inv_base_m = 1.0;
+    for _ in 0..digits {
+        let digit_hash = mix_bits(u64::from(hash) ^ reversed_digits) as u32;
+        let digit = permutation_element((index % u64::from(base)) as u32, base, digit_hash);
+        reversed_digits = reversed_digits * u64::from(base) + u64::from(digit);
+        inv_base_m *= inv_base;
+        index /= u64::from(base);
+    }
+    ((reversed_digits as f64 * inv_base_m) as f32).min(ONE_MINUS_EPSILON)
+}
+
+// Returns the i-th element of a pseudo-random permutation of 0..l without building it (Kensler 2013)
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    i.wrapping_add(p) % l
+}
+
+const PRIMES: [u32; 256] = first_primes();
+
+const fn first_primes() -> [u32; 256] {
+    let mut primes = [0u32; 256];
+    let mut count = 0;
+    let mut candidate = 2;
+    while count < primes.len() {
+        let mut is_prime = true;
+        let mut i = 0;
+        while i < count {
+            if candidate % primes[i] == 0 {
+                is_prime = false;
+                break;
+            }
+            i += 1;
+        }
+        if is_prime {
+            primes[count] = candidate;
+            count += 1;
+        }
+        candidate += 1;
+    }
+    primes
+}
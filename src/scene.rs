@@ -0,0 +1,77 @@
+use crate::{bvh_node::BvhNode, light::Light, light_bvh::LightBvh, object::{HitRecord, Object}, ray::Ray, vec3::Vec3};
+use rand::RngCore;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub enum Background {
+    // Blend from the first color looking straight down to the second looking straight up
+    Gradient(Vec3, Vec3),
+    Constant(Vec3),
+}
+
+impl Background {
+    pub fn radiance(&self, direction: &Vec3) -> Vec3 {
+        match self {
+            Background::Gradient(bottom, top) => {
+                let t = 0.5 * (direction.normalize().y() + 1.0);
+                bottom.lerp(top, t)
+            }
+            Background::Constant(color) => *color,
+        }
+    }
+}
+
+pub struct Scene {
+    pub world: Box<dyn Object>,
+    pub lights: Vec<Arc<dyn Light>>,
+    pub background: Background,
+    light_bvh: LightBvh,
+}
+
+impl Scene {
+    pub fn new(
+        mut objects: Vec<Box<dyn Object>>,
+        lights: Vec<Arc<dyn Light>>,
+        background: Background,
+        rng: &mut dyn RngCore,
+    ) -> Self {
+        let world = if objects.len() == 1 {
+            objects.pop().unwrap()
+        } else {
+            Box::new(BvhNode::new(objects, rng))
+        };
+        let light_bvh = LightBvh::new(&lights);
+        Scene {
+            world,
+            lights,
+            background,
+            light_bvh,
+        }
+    }
+
+    pub fn background(&self, ray: &Ray) -> Vec3 {
+        self.background.radiance(&ray.direction())
+    }
+
+    // The light standing in for the background, when it has been added to the light list
+    pub fn environment_light(&self) -> Option<&dyn Light> {
+        self.lights.iter().find(|light| light.is_infinite()).map(|light| light.as_ref())
+    }
+
+    // Picks a light to light the surface at point with the given normal, favouring those likely to
+    // contribute most, and returns it with the probability it had of being chosen
+    pub fn sample_light(&self, point: &Vec3, normal: &Vec3, u: f32) -> Option<(&dyn Light, f32)> {
+        self.light_bvh.sample(point, normal, u)
+    }
+
+    pub fn light_pmf(&self, point: &Vec3, normal: &Vec3, light: &dyn Light) -> f32 {
+        self.light_bvh.pmf(point, normal, light)
+    }
+
+    // True when nothing blocks the segment leaving a surface along the unit direction wi for distance. It
+    // stops just short, relative to its length, so the surface of the light at its end doesn't count.
+    pub fn unoccluded(&self, hit_record: &HitRecord, wi: &Vec3, distance: f32, time: f32) -> bool {
+        let shadow_ray = hit_record.spawn_ray(wi, time);
+        self.world.hit(&shadow_ray, 0.0, distance * (1.0 - 1e-3)).is_none()
+    }
+}
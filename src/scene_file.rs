@@ -0,0 +1,810 @@
+// Plain text scene description. Each line is a directive followed by named parameters, each a keyword
+// and its values, and anything after a '#' is a comment. docs/scene-format.md describes every directive.
+use crate::{
+    bump::{NormalPerturbation, ShadingNormal},
+    camera::CameraSettings,
+    ies::IesProfile,
+    layered::{Coated, MixMaterial},
+    light::{DirectionalLight, EnvironmentLight, Light, MeshLight, PointLight, RectLight, SphereLight, SpotLight},
+    material::{
+        Dielectric, DiffuseLight, Lambertian, Material, Metal, MetalPreset, OrenNayar, RefractiveIndex, ThinFilm,
+    },
+    object::{AlphaMask, Object},
+    principled::Principled,
+    rect::Rect,
+    rng::Pcg32,
+    scene::{Background, Scene},
+    sphere::{MovingSphere, Sphere},
+    subsurface::Subsurface,
+    texture::{Checker, ColorEncoding, Constant, Image, Texture},
+    triangle::TriangleMesh,
+    vec3::Vec3,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+pub struct SceneDescription {
+    pub scene: Scene,
+    // None when the file leaves the camera to the caller
+    pub camera: Option<CameraSettings>,
+}
+
+// Materials are kept as descriptions so each object using one gets its own instance
+enum MaterialSpec {
+    Lambertian(Vec3),
+    // Albedo, facet slope deviation in radians and whether to use the full model
+    OrenNayar(Vec3, f32, bool),
+    Metal(Vec3, f32, f32, Option<ThinFilm>),
+    // Complex index of refraction and roughness along each tangent
+    Conductor(Vec3, Vec3, f32, f32, Option<ThinFilm>),
+    Dielectric {
+        refractive_index: RefractiveIndex,
+        roughness: (f32, f32),
+        absorption: Vec3,
+        thin_film: Option<ThinFilm>,
+    },
+    Subsurface {
+        albedo: Vec3,
+        mean_free_path: Vec3,
+        refractive_index: f32,
+        roughness: (f32, f32),
+    },
+    Light(Vec3, bool),
+    Principled(Principled),
+    ShadingNormal(Arc<MaterialSpec>, NormalPerturbation),
+    // The second material's share and the two materials
+    Mix(Arc<dyn Texture>, Arc<MaterialSpec>, Arc<MaterialSpec>),
+    Coated {
+        base: Arc<MaterialSpec>,
+        refractive_index: f32,
+        roughness: (f32, f32),
+        absorption: Vec3,
+    },
+}
+
+impl MaterialSpec {
+    fn create(&self) -> Box<dyn Material> {
+        match *self {
+            MaterialSpec::Lambertian(albedo) => Box::new(Lambertian::new(albedo)),
+            MaterialSpec::OrenNayar(albedo, sigma, interreflection) => {
+                Box::new(OrenNayar::new(albedo, sigma).with_interreflection(interreflection))
+            }
+            MaterialSpec::Metal(albedo, roughness_u, roughness_v, ref thin_film) => {
+                let metal = Metal::anisotropic(albedo, roughness_u, roughness_v);
+                Box::new(match thin_film {
+                    Some(thin_film) => metal.with_thin_film(thin_film.clone()),
+                    None => metal,
+                })
+            }
+            MaterialSpec::Conductor(eta, k, roughness_u, roughness_v, ref thin_film) => {
+                let metal = Metal::conductor(eta, k, roughness_u, roughness_v);
+                Box::new(match thin_film {
+                    Some(thin_film) => metal.with_thin_film(thin_film.clone()),
+                    None => metal,
+                })
+            }
+            MaterialSpec::Dielectric {
+                refractive_index,
+                roughness,
+                absorption,
+                ref thin_film,
+            } => {
+                let dielectric = Dielectric::with_index(refractive_index)
+                    .with_roughness(roughness.0, roughness.1)
+                    .with_absorption(absorption);
+                Box::new(match thin_film {
+                    Some(thin_film) => dielectric.with_thin_film(thin_film.clone()),
+                    None => dielectric,
+                })
+            }
+            MaterialSpec::Subsurface {
+                albedo,
+                mean_free_path,
+                refractive_index,
+                roughness,
+            } => Box::new(
+                Subsurface::new(albedo, mean_free_path)
+                    .with_refractive_index(refractive_index)
+                    .with_roughness(roughness.0, roughness.1),
+            ),
+            MaterialSpec::Light(emit, two_sided) => Box::new(DiffuseLight::new(emit).with_two_sided(two_sided)),
+            MaterialSpec::Principled(ref principled) => Box::new(principled.clone()),
+            MaterialSpec::ShadingNormal(ref base, ref perturbation) => {
+                Box::new(ShadingNormal::new(base.create(), perturbation.clone()))
+            }
+            MaterialSpec::Mix(ref weight, ref first, ref second) => {
+                Box::new(MixMaterial::new(first.create(), second.create(), weight.clone()))
+            }
+            MaterialSpec::Coated {
+                ref base,
+                refractive_index,
+                roughness,
+                absorption,
+            } => Box::new(
+                Coated::new(base.create(), refractive_index)
+                    .with_roughness(roughness.0, roughness.1)
+                    .with_absorption(absorption),
+            ),
+        }
+    }
+}
+
+type PrincipledSetter = fn(Principled, Arc<dyn Texture>) -> Principled;
+
+enum LightOutput {
+    Intensity(Vec3),
+    Power(Vec3),
+}
+
+impl LightOutput {
+    // Power is only turned into an intensity once the light's shape is known
+    fn intensity(&self) -> Vec3 {
+        match *self {
+            LightOutput::Intensity(intensity) => intensity,
+            LightOutput::Power(_) => Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+// Parameters of one directive, checked off as they're read so misspelled ones can be reported
+struct Params<'a> {
+    values: HashMap<&'a str, Vec<&'a str>>,
+    used: Vec<&'a str>,
+}
+
+impl<'a> Params<'a> {
+    fn parse(tokens: &[&'a str]) -> Result<Self, String> {
+        let mut values = HashMap::new();
+        let mut tokens = tokens.iter().peekable();
+        while let Some(&key) = tokens.next() {
+            if key.parse::<f32>().is_ok() {
+                return Err(format!("Expected a parameter name but found '{}'", key));
+            }
+            let mut list = vec![];
+            while let Some(&&value) = tokens.peek() {
+                if value.parse::<f32>().is_err() {
+                    break;
+                }
+                list.push(value);
+                tokens.next();
+            }
+            // A parameter without numbers takes a single word instead
+            if list.is_empty() {
+                let value = tokens.next().ok_or_else(|| format!("Missing value for '{}'", key))?;
+                list.push(value);
+            }
+            if values.insert(key, list).is_some() {
+                return Err(format!("Parameter '{}' given twice", key));
+            }
+        }
+        Ok(Params { values, used: vec![] })
+    }
+
+    fn get(&mut self, key: &'a str) -> Option<&[&'a str]> {
+        self.used.push(key);
+        self.values.get(key).map(|values| values.as_slice())
+    }
+
+    fn floats(&mut self, key: &'a str, count: usize) -> Result<Option<Vec<f32>>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(values) if values.len() == count => values
+                .iter()
+                .map(|value| value.parse().map_err(|_| format!("Invalid number '{}' for '{}'", value, key)))
+                .collect::<Result<Vec<f32>, String>>()
+                .map(Some),
+            Some(values) => Err(format!("'{}' takes {} numbers but was given {}", key, count, values.len())),
+        }
+    }
+
+    // Any number of values
+    fn list(&mut self, key: &'a str) -> Result<Option<Vec<f32>>, String> {
+        let count = self.values.get(key).map_or(0, |values| values.len());
+        self.floats(key, count)
+    }
+
+    fn float(&mut self, key: &'a str) -> Result<Option<f32>, String> {
+        Ok(self.floats(key, 1)?.map(|values| values[0]))
+    }
+
+    fn vec3(&mut self, key: &'a str) -> Result<Option<Vec3>, String> {
+        Ok(self.floats(key, 3)?.map(|values| Vec3::new(values[0], values[1], values[2])))
+    }
+
+    fn word(&mut self, key: &'a str) -> Result<Option<&'a str>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some([value]) => Ok(Some(*value)),
+            Some(_) => Err(format!("'{}' takes a single value", key)),
+        }
+    }
+
+    // Microfacet alpha, either 'roughness' for both tangent directions or 'roughness_u' and
+    // 'roughness_v' for anisotropic surfaces. Smooth when not given.
+    fn roughness(&mut self) -> Result<(f32, f32), String> {
+        match (self.float("roughness")?, self.float("roughness_u")?, self.float("roughness_v")?) {
+            (Some(roughness), None, None) => Ok((roughness, roughness)),
+            (None, Some(u), Some(v)) => Ok((u, v)),
+            (None, None, None) => Ok((0.0, 0.0)),
+            _ => Err("Expected either 'roughness' or both 'roughness_u' and 'roughness_v'".to_string()),
+        }
+    }
+
+    fn boolean(&mut self, key: &'a str) -> Result<Option<bool>, String> {
+        match self.word(key)? {
+            None => Ok(None),
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(value) => Err(format!("Expected true or false for '{}' but found '{}'", key, value)),
+        }
+    }
+
+    fn required_float(&mut self, key: &'a str) -> Result<f32, String> {
+        self.float(key)?.ok_or_else(|| format!("Missing '{}'", key))
+    }
+
+    fn required_vec3(&mut self, key: &'a str) -> Result<Vec3, String> {
+        self.vec3(key)?.ok_or_else(|| format!("Missing '{}'", key))
+    }
+
+    fn required_word(&mut self, key: &'a str) -> Result<&'a str, String> {
+        self.word(key)?.ok_or_else(|| format!("Missing '{}'", key))
+    }
+
+    // Intensity in watts per steradian, or power in watts
+    fn intensity_or_power(&mut self) -> Result<LightOutput, String> {
+        match (self.vec3("intensity")?, self.vec3("power")?) {
+            (Some(intensity), None) => Ok(LightOutput::Intensity(intensity)),
+            (None, Some(power)) => Ok(LightOutput::Power(power)),
+            _ => Err("Expected exactly one of 'intensity' or 'power'".to_string()),
+        }
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.values.keys().find(|key| !self.used.contains(key)) {
+            Some(key) => Err(format!("Unknown parameter '{}'", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SceneBuilder {
+    objects: Vec<Box<dyn Object>>,
+    lights: Vec<Arc<dyn Light>>,
+    materials: HashMap<String, Arc<MaterialSpec>>,
+    background: Option<Background>,
+    environment_light: bool,
+    camera: Option<CameraSettings>,
+    // Directory of the scene file, which other files are found relative to
+    directory: PathBuf,
+    profiles: HashMap<PathBuf, Arc<IesProfile>>,
+    textures: HashMap<String, Arc<dyn Texture>>,
+}
+
+impl SceneBuilder {
+    fn material(&self, name: &str) -> Result<Box<dyn Material>, String> {
+        self.material_spec(name).map(|spec| spec.create())
+    }
+
+    fn material_spec(&self, name: &str) -> Result<Arc<MaterialSpec>, String> {
+        self.materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown material '{}'", name))
+    }
+
+    // Texture parameter given as a single number, a color, or the name of a texture
+    fn texture<'a>(&self, params: &mut Params<'a>, key: &'a str) -> Result<Option<Arc<dyn Texture>>, String> {
+        let values = match params.get(key) {
+            Some(values) => values.to_vec(),
+            None => return Ok(None),
+        };
+        let numbers: Result<Vec<f32>, _> = values.iter().map(|value| value.parse::<f32>()).collect();
+        let texture: Arc<dyn Texture> = match (values.as_slice(), numbers) {
+            (_, Ok(numbers)) if numbers.len() == 1 => Arc::new(Constant::scalar(numbers[0])),
+            (_, Ok(numbers)) if numbers.len() == 3 => Arc::new(Constant::new(Vec3::new(numbers[0], numbers[1], numbers[2]))),
+            ([name], Err(_)) => self
+                .textures
+                .get(*name)
+                .cloned()
+                .ok_or_else(|| format!("Unknown texture '{}'", name))?,
+            _ => return Err(format!("'{}' takes a number, a color or a texture name", key)),
+        };
+        Ok(Some(texture))
+    }
+
+    // Shading normals from a 'normal_map' texture, or from a 'bump' height texture scaled by 'bump_scale'
+    fn normal_perturbation<'a>(&self, params: &mut Params<'a>) -> Result<Option<NormalPerturbation>, String> {
+        match (self.texture(params, "normal_map")?, self.texture(params, "bump")?, params.float("bump_scale")?) {
+            (Some(texture), None, None) => Ok(Some(NormalPerturbation::NormalMap(texture))),
+            (None, Some(height), scale) => Ok(Some(NormalPerturbation::Bump {
+                height,
+                scale: scale.unwrap_or(1.0),
+            })),
+            (None, None, None) => Ok(None),
+            _ => Err("Expected either 'normal_map' or 'bump' with an optional 'bump_scale'".to_string()),
+        }
+    }
+
+    // Cutout from an 'alpha' opacity texture, which rays pass through where it is black
+    fn alpha_mask<'a>(&self, params: &mut Params<'a>) -> Result<Option<AlphaMask>, String> {
+        Ok(self.texture(params, "alpha")?.map(AlphaMask::new))
+    }
+
+    // Interference coating given by 'film_thickness' in nanometres, a number or a texture, and 'film_ior'
+    fn thin_film<'a>(&self, params: &mut Params<'a>) -> Result<Option<ThinFilm>, String> {
+        match (self.texture(params, "film_thickness")?, params.float("film_ior")?) {
+            (Some(thickness), film_ior) => Ok(Some(ThinFilm::new(thickness, film_ior.unwrap_or(1.33)))),
+            (None, None) => Ok(None),
+            (None, Some(_)) => Err("'film_ior' needs a 'film_thickness'".to_string()),
+        }
+    }
+
+    // Photometric profile named by the optional 'profile' parameter, each file only being read once, for a
+    // light pointing down. Its horizontal angle zero is towards 'profile_c0', +x by default.
+    fn profile<'a>(&mut self, params: &mut Params<'a>, down: &Vec3) -> Result<Option<(Arc<IesProfile>, Vec3)>, String> {
+        let path = match params.word("profile")? {
+            Some(file) => self.directory.join(file),
+            None => return Ok(None),
+        };
+        let horizontal_zero = match params.vec3("profile_c0")? {
+            Some(c0) if c0.cross(down).squared_length() <= 1e-8 * c0.squared_length() * down.squared_length() => {
+                return Err("'profile_c0' can't be parallel to the direction the light points".to_string())
+            }
+            Some(c0) => c0,
+            None => Vec3::new(1.0, 0.0, 0.0),
+        };
+        if let Some(profile) = self.profiles.get(&path) {
+            return Ok(Some((profile.clone(), horizontal_zero)));
+        }
+        let source = fs::read_to_string(&path).map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+        let profile = Arc::new(IesProfile::parse(&source).map_err(|error| format!("{}: {}", path.display(), error))?);
+        self.profiles.insert(path, profile.clone());
+        Ok(Some((profile, horizontal_zero)))
+    }
+
+    // Triangles given inline by positions and indices, or loaded from an OBJ file relative to the scene file
+    fn mesh_geometry<'a>(&self, params: &mut Params<'a>) -> Result<(Vec<Vec3>, Vec<[usize; 3]>), String> {
+        let (positions, indices) = match (params.word("file")?, params.list("positions")?, params.list("indices")?) {
+            (Some(file), None, None) => load_obj(&self.directory.join(file))?,
+            (None, Some(positions), Some(indices)) => {
+                if positions.len() % 3 != 0 || indices.len() % 3 != 0 {
+                    return Err("Mesh positions and indices must come in threes".to_string());
+                }
+                let positions = positions.chunks(3).map(|p| Vec3::new(p[0], p[1], p[2])).collect();
+                if indices.iter().any(|&index| index < 0.0 || index.fract() != 0.0) {
+                    return Err("Mesh indices must be whole numbers".to_string());
+                }
+                let indices = indices
+                    .chunks(3)
+                    .map(|i| [i[0] as usize, i[1] as usize, i[2] as usize])
+                    .collect();
+                (positions, indices)
+            }
+            _ => return Err("Expected either 'file' or both 'positions' and 'indices'".to_string()),
+        };
+        if indices.is_empty() {
+            return Err("Mesh has no triangles".to_string());
+        }
+        if indices.iter().flatten().any(|&index| index >= positions.len()) {
+            return Err("Mesh index out of range".to_string());
+        }
+        Ok((positions, indices))
+    }
+
+    fn directive(&mut self, name: &str, tokens: &[&str]) -> Result<(), String> {
+        match name {
+            "camera" => {
+                let mut params = Params::parse(tokens)?;
+                let mut camera = CameraSettings::default();
+                camera.look_from = params.vec3("look_from")?.unwrap_or(camera.look_from);
+                camera.look_at = params.vec3("look_at")?.unwrap_or(camera.look_at);
+                camera.v_up = params.vec3("up")?.unwrap_or(camera.v_up);
+                camera.v_fov = params.float("fov")?.map(f32::to_radians).unwrap_or(camera.v_fov);
+                camera.aperture = params.float("aperture")?.unwrap_or(camera.aperture);
+                camera.focus_dist = params.float("focus_dist")?.unwrap_or(camera.focus_dist);
+                if let Some(shutter) = params.floats("shutter", 2)? {
+                    camera.open_time = shutter[0];
+                    camera.close_time = shutter[1];
+                }
+                params.finish()?;
+                self.camera = Some(camera);
+            }
+            "background" => {
+                let mut params = Params::parse(tokens)?;
+                self.background = Some(match (params.vec3("constant")?, params.floats("gradient", 6)?) {
+                    (Some(color), None) => Background::Constant(color),
+                    (None, Some(colors)) => Background::Gradient(
+                        Vec3::new(colors[0], colors[1], colors[2]),
+                        Vec3::new(colors[3], colors[4], colors[5]),
+                    ),
+                    _ => return Err("Expected exactly one of 'constant' or 'gradient'".to_string()),
+                });
+                params.finish()?;
+            }
+            "environment_light" => {
+                Params::parse(tokens)?.finish()?;
+                self.environment_light = true;
+            }
+            "texture" => {
+                let (texture_name, kind, tokens) = match tokens {
+                    [texture_name, kind, tokens @ ..] => (texture_name, kind, tokens),
+                    _ => return Err("Expected a texture name and type".to_string()),
+                };
+                let mut params = Params::parse(tokens)?;
+                let required = |texture: Option<Arc<dyn Texture>>, key: &str| texture.ok_or_else(|| format!("Missing '{}'", key));
+                let texture: Arc<dyn Texture> = match *kind {
+                    "constant" => required(self.texture(&mut params, "value")?, "value")?,
+                    "checker" => Arc::new(Checker::new(
+                        required(self.texture(&mut params, "even")?, "even")?,
+                        required(self.texture(&mut params, "odd")?, "odd")?,
+                        params.float("scale")?.unwrap_or(10.0),
+                    )),
+                    // PPM files, decoded from sRGB unless given 'encoding linear', as data like normal maps
+                    // should be
+                    "image" => {
+                        let path = self.directory.join(params.required_word("file")?);
+                        let data = fs::read(&path).map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+                        let encoding = match params.word("encoding")? {
+                            Some(encoding) => encoding.parse::<ColorEncoding>()?,
+                            None => ColorEncoding::Srgb,
+                        };
+                        let image = Image::parse_ppm(&data, encoding);
+                        Arc::new(image.map_err(|error| format!("{}: {}", path.display(), error))?)
+                    }
+                    _ => return Err(format!("Unknown texture type '{}'", kind)),
+                };
+                params.finish()?;
+                self.textures.insert(texture_name.to_string(), texture);
+            }
+            "material" => {
+                let (material_name, kind, tokens) = match tokens {
+                    [material_name, kind, tokens @ ..] => (material_name, kind, tokens),
+                    _ => return Err("Expected a material name and type".to_string()),
+                };
+                let mut params = Params::parse(tokens)?;
+                let material = match *kind {
+                    "lambertian" => MaterialSpec::Lambertian(params.required_vec3("albedo")?),
+                    // Sigma is the standard deviation of facet slopes, in degrees
+                    "oren_nayar" => {
+                        if params.get("roughness").is_some() {
+                            return Err("oren_nayar takes 'sigma', in degrees, rather than 'roughness'".to_string());
+                        }
+                        MaterialSpec::OrenNayar(
+                            params.required_vec3("albedo")?,
+                            params.float("sigma")?.unwrap_or(20.0).to_radians(),
+                            params.boolean("interreflection")?.unwrap_or(false),
+                        )
+                    }
+                    "metal" => {
+                        if params.get("fuzz").is_some() {
+                            return Err("'fuzz' has been replaced by 'roughness', the GGX alpha".to_string());
+                        }
+                        let albedo = params.required_vec3("albedo")?;
+                        let (roughness_u, roughness_v) = params.roughness()?;
+                        MaterialSpec::Metal(albedo, roughness_u, roughness_v, self.thin_film(&mut params)?)
+                    }
+                    "conductor" => {
+                        let (eta, k) = match (params.word("preset")?, params.vec3("eta")?, params.vec3("k")?) {
+                            (Some(preset), None, None) => preset.parse::<MetalPreset>()?.eta_k(),
+                            (None, Some(eta), Some(k)) => (eta, k),
+                            _ => return Err("Expected either 'preset' or both 'eta' and 'k'".to_string()),
+                        };
+                        let (roughness_u, roughness_v) = params.roughness()?;
+                        MaterialSpec::Conductor(eta, k, roughness_u, roughness_v, self.thin_film(&mut params)?)
+                    }
+                    // A constant 'ior', or dispersion by Cauchy's or Sellmeier's equation with coefficients for
+                    // wavelengths in micrometres
+                    "dielectric" => {
+                        let refractive_index = match (
+                            params.float("ior")?,
+                            params.floats("cauchy", 2)?,
+                            params.floats("sellmeier", 6)?,
+                        ) {
+                            (Some(eta), None, None) => RefractiveIndex::Constant(eta),
+                            (None, Some(cauchy), None) => RefractiveIndex::Cauchy {
+                                a: cauchy[0],
+                                b: cauchy[1],
+                            },
+                            (None, None, Some(sellmeier)) => RefractiveIndex::Sellmeier {
+                                b: [sellmeier[0], sellmeier[1], sellmeier[2]],
+                                c: [sellmeier[3], sellmeier[4], sellmeier[5]],
+                            },
+                            _ => return Err("Expected one of 'ior', 'cauchy' or 'sellmeier'".to_string()),
+                        };
+                        let roughness = params.roughness()?;
+                        let absorption = params.vec3("absorption")?.unwrap_or_else(|| Vec3::new(0.0, 0.0, 0.0));
+                        MaterialSpec::Dielectric {
+                            refractive_index,
+                            roughness,
+                            absorption,
+                            thin_film: self.thin_film(&mut params)?,
+                        }
+                    }
+                    // Needs closed surfaces whose normals point outwards, as the medium fills their inside
+                    "subsurface" => MaterialSpec::Subsurface {
+                        albedo: params.required_vec3("albedo")?,
+                        mean_free_path: params.required_vec3("mean_free_path")?,
+                        refractive_index: params.float("ior")?.unwrap_or(1.4),
+                        roughness: params.roughness()?,
+                    },
+                    "principled" => {
+                        let base_color = self.texture(&mut params, "base_color")?;
+                        let mut principled = Principled::new(
+                            base_color.unwrap_or_else(|| Arc::new(Constant::scalar(0.8))),
+                        );
+                        let setters: [(&str, PrincipledSetter); 9] = [
+                            ("metallic", Principled::with_metallic),
+                            ("roughness", Principled::with_roughness),
+                            ("specular", Principled::with_specular),
+                            ("specular_tint", Principled::with_specular_tint),
+                            ("sheen", Principled::with_sheen),
+                            ("clearcoat", Principled::with_clearcoat),
+                            ("clearcoat_gloss", Principled::with_clearcoat_gloss),
+                            ("transmission", Principled::with_transmission),
+                            ("ior", Principled::with_refractive_index),
+                        ];
+                        for (key, setter) in setters {
+                            if let Some(texture) = self.texture(&mut params, key)? {
+                                principled = setter(principled, texture);
+                            }
+                        }
+                        MaterialSpec::Principled(principled)
+                    }
+                    // Mixed and coated materials refer to materials defined earlier
+                    "mix" => {
+                        let first = self.material_spec(params.required_word("a")?)?;
+                        let second = self.material_spec(params.required_word("b")?)?;
+                        if first.create().medium().is_some() && second.create().medium().is_some() {
+                            return Err("Only one of the materials in a mix can have a medium inside".to_string());
+                        }
+                        let weight = self
+                            .texture(&mut params, "weight")?
+                            .unwrap_or_else(|| Arc::new(Constant::scalar(0.5)));
+                        MaterialSpec::Mix(weight, first, second)
+                    }
+                    "coated" => MaterialSpec::Coated {
+                        base: self.material_spec(params.required_word("base")?)?,
+                        refractive_index: params.float("ior")?.unwrap_or(1.5),
+                        roughness: params.roughness()?,
+                        absorption: params.vec3("absorption")?.unwrap_or_else(|| Vec3::new(0.0, 0.0, 0.0)),
+                    },
+                    "light" => MaterialSpec::Light(
+                        params.required_vec3("emit")?,
+                        params.boolean("two_sided")?.unwrap_or(false),
+                    ),
+                    _ => return Err(format!("Unknown material type '{}'", kind)),
+                };
+                let material = match self.normal_perturbation(&mut params)? {
+                    Some(perturbation) => MaterialSpec::ShadingNormal(Arc::new(material), perturbation),
+                    None => material,
+                };
+                params.finish()?;
+                self.materials.insert(material_name.to_string(), Arc::new(material));
+            }
+            "sphere" => {
+                let mut params = Params::parse(tokens)?;
+                let center = params.required_vec3("center")?;
+                let radius = params.required_float("radius")?;
+                let material = self.material(params.required_word("material")?)?;
+                let alpha_mask = self.alpha_mask(&mut params)?;
+                // Spheres given a second center move between the two while the shutter is open
+                let object: Box<dyn Object> = match params.vec3("center1")? {
+                    Some(center1) => {
+                        let time = params.floats("time", 2)?.unwrap_or_else(|| vec![0.0, 1.0]);
+                        let mut sphere = MovingSphere::new(center, center1, radius, time[0], time[1], material);
+                        if let Some(alpha_mask) = alpha_mask {
+                            sphere = sphere.with_alpha_mask(alpha_mask);
+                        }
+                        Box::new(sphere)
+                    }
+                    None => {
+                        let mut sphere = Sphere::new(center, radius, material);
+                        if let Some(alpha_mask) = alpha_mask {
+                            sphere = sphere.with_alpha_mask(alpha_mask);
+                        }
+                        Box::new(sphere)
+                    }
+                };
+                params.finish()?;
+                self.objects.push(object);
+            }
+            "sphere_light" => {
+                let mut params = Params::parse(tokens)?;
+                let center = params.required_vec3("center")?;
+                let radius = params.required_float("radius")?;
+                let emit = params.required_vec3("emit")?;
+                params.finish()?;
+                let light: Arc<dyn Light> = Arc::new(SphereLight::new(center, radius, emit));
+                self.objects.push(Box::new(
+                    Sphere::new(center, radius, Box::new(DiffuseLight::new(emit))).with_light(light.clone()),
+                ));
+                self.lights.push(light);
+            }
+            "rect" => {
+                let mut params = Params::parse(tokens)?;
+                let corner = params.required_vec3("corner")?;
+                let edge1 = params.required_vec3("edge1")?;
+                let edge2 = params.required_vec3("edge2")?;
+                let material = self.material(params.required_word("material")?)?;
+                let mut rect = Rect::new(corner, edge1, edge2, material);
+                if let Some(alpha_mask) = self.alpha_mask(&mut params)? {
+                    rect = rect.with_alpha_mask(alpha_mask);
+                }
+                params.finish()?;
+                self.objects.push(Box::new(rect));
+            }
+            // Area lights emit from their front, the side their edges or vertices wind counterclockwise around,
+            // unless two sided
+            "rect_light" => {
+                let mut params = Params::parse(tokens)?;
+                let corner = params.required_vec3("corner")?;
+                let edge1 = params.required_vec3("edge1")?;
+                let edge2 = params.required_vec3("edge2")?;
+                let emit = params.required_vec3("emit")?;
+                let two_sided = params.boolean("two_sided")?.unwrap_or(false);
+                params.finish()?;
+                let light: Arc<dyn Light> = Arc::new(RectLight::new(corner, edge1, edge2, emit, two_sided));
+                let material = Box::new(DiffuseLight::new(emit).with_two_sided(two_sided));
+                self.objects.push(Box::new(Rect::new(corner, edge1, edge2, material).with_light(light.clone())));
+                self.lights.push(light);
+            }
+            "mesh" => {
+                let mut params = Params::parse(tokens)?;
+                if params.get("alpha").is_some() {
+                    return Err("Meshes have no texture coordinates to map an 'alpha' texture with".to_string());
+                }
+                let (positions, indices) = self.mesh_geometry(&mut params)?;
+                let material = self.material(params.required_word("material")?)?;
+                let mesh = TriangleMesh::new(positions, indices, material);
+                params.finish()?;
+                self.objects.extend(Arc::new(mesh).triangles());
+            }
+            "mesh_light" => {
+                let mut params = Params::parse(tokens)?;
+                let (positions, indices) = self.mesh_geometry(&mut params)?;
+                let emit = params.required_vec3("emit")?;
+                let two_sided = params.boolean("two_sided")?.unwrap_or(false);
+                params.finish()?;
+                let light: Arc<dyn Light> = Arc::new(MeshLight::new(&positions, &indices, emit, two_sided));
+                let material = Box::new(DiffuseLight::new(emit).with_two_sided(two_sided));
+                let mesh = TriangleMesh::new(positions, indices, material).with_light(light.clone());
+                self.objects.extend(Arc::new(mesh).triangles());
+                self.lights.push(light);
+            }
+            "point_light" => {
+                let mut params = Params::parse(tokens)?;
+                let position = params.required_vec3("position")?;
+                let output = params.intensity_or_power()?;
+                let mut light = PointLight::new(position, output.intensity());
+                if let Some((profile, horizontal_zero)) = self.profile(&mut params, &Vec3::new(0.0, -1.0, 0.0))? {
+                    light = light.with_profile(profile, &horizontal_zero);
+                }
+                if let LightOutput::Power(power) = output {
+                    light = light.with_power(power);
+                }
+                params.finish()?;
+                self.lights.push(Arc::new(light));
+            }
+            "spot_light" => {
+                let mut params = Params::parse(tokens)?;
+                let position = params.required_vec3("position")?;
+                let direction = match (params.vec3("direction")?, params.vec3("look_at")?) {
+                    (Some(direction), None) => direction,
+                    (None, Some(look_at)) => look_at - position,
+                    _ => return Err("Expected exactly one of 'direction' or 'look_at'".to_string()),
+                };
+                let outer = params.required_float("outer")?.to_radians();
+                let inner = params.float("inner")?.map(f32::to_radians).unwrap_or(outer);
+                let output = params.intensity_or_power()?;
+                let mut light = SpotLight::new(position, direction, output.intensity(), inner, outer);
+                if let Some((profile, horizontal_zero)) = self.profile(&mut params, &direction)? {
+                    light = light.with_profile(profile, &horizontal_zero);
+                }
+                if let LightOutput::Power(power) = output {
+                    light = light.with_power(power);
+                }
+                params.finish()?;
+                self.lights.push(Arc::new(light));
+            }
+            "directional_light" => {
+                let mut params = Params::parse(tokens)?;
+                let direction = params.required_vec3("direction")?;
+                let irradiance = params.required_vec3("irradiance")?;
+                params.finish()?;
+                self.lights.push(Arc::new(DirectionalLight::new(direction, irradiance)));
+            }
+            _ => return Err(format!("Unknown directive '{}'", name)),
+        }
+        Ok(())
+    }
+
+    fn build(mut self, seed: u64) -> Result<SceneDescription, String> {
+        if self.objects.is_empty() {
+            return Err("Scene has no objects".to_string());
+        }
+        let background = self.background.unwrap_or(Background::Constant(Vec3::new(0.0, 0.0, 0.0)));
+        if self.environment_light {
+            self.lights.push(Arc::new(EnvironmentLight::new(background.clone())));
+        }
+        let mut rng = Pcg32::new(seed, 0);
+        Ok(SceneDescription {
+            scene: Scene::new(self.objects, self.lights, background, &mut rng),
+            camera: self.camera,
+        })
+    }
+}
+
+pub fn load_scene(path: &str, seed: u64) -> Result<SceneDescription, Error> {
+    let source = fs::read_to_string(path)?;
+    let invalid = |message: String| Error::new(ErrorKind::InvalidData, format!("{}: {}", path, message));
+    let mut builder = SceneBuilder {
+        directory: Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default(),
+        ..SceneBuilder::default()
+    };
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if let Some((name, tokens)) = tokens.split_first() {
+            builder
+                .directive(name, tokens)
+                .map_err(|message| invalid(format!("line {}: {}", index + 1, message)))?;
+        }
+    }
+    builder.build(seed).map_err(invalid)
+}
+
+// Vertex positions and faces of a Wavefront OBJ file, with polygons split into fans of triangles.
+// Everything else in the file is ignored.
+fn load_obj(path: &Path) -> Result<(Vec<Vec3>, Vec<[usize; 3]>), String> {
+    let source = fs::read_to_string(path).map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+    let invalid = |line: usize, message: &str| format!("{}: line {}: {}", path.display(), line + 1, message);
+    let mut positions = vec![];
+    let mut indices = vec![];
+    for (line_index, line) in source.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let values: Vec<f32> = tokens
+                    .take(3)
+                    .map(|value| value.parse().map_err(|_| invalid(line_index, "invalid vertex")))
+                    .collect::<Result<_, _>>()?;
+                if values.len() != 3 {
+                    return Err(invalid(line_index, "vertex needs three coordinates"));
+                }
+                positions.push(Vec3::new(values[0], values[1], values[2]));
+            }
+            Some("f") => {
+                // Each vertex is v, v/vt, v//vn or v/vt/vn, counting from one or back from the end when negative
+                let face: Vec<usize> = tokens
+                    .map(|vertex| {
+                        let index: i64 = vertex
+                            .split('/')
+                            .next()
+                            .and_then(|index| index.parse().ok())
+                            .ok_or_else(|| invalid(line_index, "invalid face"))?;
+                        match index {
+                            index if index > 0 => Ok(index as usize - 1),
+                            index if index < 0 && (-index) as usize <= positions.len() => {
+                                Ok(positions.len() - (-index) as usize)
+                            }
+                            _ => Err(invalid(line_index, "invalid face index")),
+                        }
+                    })
+                    .collect::<Result<_, _>>()?;
+                if face.len() < 3 {
+                    return Err(invalid(line_index, "face needs at least three vertices"));
+                }
+                for i in 1..face.len() - 1 {
+                    indices.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((positions, indices))
+}
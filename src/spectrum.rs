@@ -0,0 +1,478 @@
+// Spectral rendering. Paths carry radiance at three sampled wavelengths in the channels of a Vec3, and the
+// scene's RGB colors are turned into smooth spectra with the sigmoid polynomials of Jakob and Hanika, "A
+// Low-Dimensional Function Space for Efficient Spectral Upsampling" (2019). Spectra are converted back to
+// linear sRGB through CIE XYZ before they reach the film.
+use crate::{sampler::Sampler, vec3::Vec3};
+use std::sync::OnceLock;
+
+const LAMBDA_MIN: f32 = 360.0;
+const LAMBDA_MAX: f32 = 830.0;
+const WAVELENGTH_COUNT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    Rgb,
+    Spectral,
+}
+
+impl ColorMode {
+    // Picks what a new camera path carries, drawing its wavelengths from the sampler when spectral
+    pub fn start_path(self, sampler: &mut dyn Sampler) -> PathColor {
+        match self {
+            ColorMode::Rgb => PathColor::Rgb,
+            ColorMode::Spectral => PathColor::Spectral(SampledWavelengths::sample_visible(sampler.get_1d())),
+        }
+    }
+}
+
+impl std::str::FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb" => Ok(ColorMode::Rgb),
+            "spectral" => Ok(ColorMode::Spectral),
+            _ => Err(format!("Unknown color mode '{}'", s)),
+        }
+    }
+}
+
+// What the Vec3 values along a path mean: plain RGB, or a spectrum's values at the path's wavelengths
+#[derive(Clone, Copy, Debug)]
+pub enum PathColor {
+    Rgb,
+    Spectral(SampledWavelengths),
+}
+
+impl PathColor {
+    // Converts an unbounded RGB quantity such as an emitter's radiance or an extinction coefficient into
+    // what the path carries
+    pub fn upsample(&self, rgb: Vec3) -> Vec3 {
+        match self {
+            PathColor::Rgb => rgb,
+            PathColor::Spectral(wavelengths) => wavelengths.upsample(rgb),
+        }
+    }
+
+    // Converts a reflectance or transmittance, whose spectrum stays within [0, 1] like the color itself
+    pub fn upsample_albedo(&self, rgb: Vec3) -> Vec3 {
+        match self {
+            PathColor::Rgb => rgb,
+            PathColor::Spectral(wavelengths) => wavelengths.upsample_albedo(rgb),
+        }
+    }
+
+    // Converts a BSDF value: a reflectance times a factor for the lobe's shape, which can exceed one and is
+    // taken out first so the spectrum is no brighter than the color's brightest channel
+    pub fn upsample_bsdf(&self, rgb: Vec3) -> Vec3 {
+        let scale = rgb.max_component().max(1.0);
+        self.upsample_albedo(rgb.scalar_mul(1.0 / scale)).scalar_mul(scale)
+    }
+
+    // Linear sRGB for a value the path carries
+    pub fn to_rgb(self, value: Vec3) -> Vec3 {
+        match self {
+            PathColor::Rgb => value,
+            PathColor::Spectral(wavelengths) => wavelengths.to_rgb(value),
+        }
+    }
+
+    // All of the path's wavelengths, for quantities computed at each one directly rather than upsampled.
+    // None when rendering in RGB.
+    pub fn wavelengths(&self) -> Option<[f32; WAVELENGTH_COUNT]> {
+        match self {
+            PathColor::Rgb => None,
+            PathColor::Spectral(wavelengths) => Some(wavelengths.lambda),
+        }
+    }
+
+    // The hero wavelength, which dispersive materials are evaluated at. None when rendering in RGB.
+    pub fn wavelength(&self) -> Option<f32> {
+        match self {
+            PathColor::Rgb => None,
+            PathColor::Spectral(wavelengths) => Some(wavelengths.lambda[0]),
+        }
+    }
+
+    // Drops all but the hero wavelength, for when the path takes a direction only valid for that one
+    pub fn terminate_secondary(&mut self) {
+        if let PathColor::Spectral(wavelengths) = self {
+            wavelengths.terminate_secondary();
+        }
+    }
+}
+
+// Wavelengths in nanometres, with the density each was sampled with. The first is the hero wavelength.
+#[derive(Clone, Copy, Debug)]
+pub struct SampledWavelengths {
+    lambda: [f32; WAVELENGTH_COUNT],
+    pdf: [f32; WAVELENGTH_COUNT],
+}
+
+impl SampledWavelengths {
+    // The others are offset from the hero by even steps of u, so together they stratify the visible
+    // range, each importance sampled towards where the eye is most sensitive
+    pub fn sample_visible(u: f32) -> Self {
+        let mut lambda = [0.0; WAVELENGTH_COUNT];
+        let mut pdf = [0.0; WAVELENGTH_COUNT];
+        for (i, (lambda, pdf)) in lambda.iter_mut().zip(pdf.iter_mut()).enumerate() {
+            let up = (u + i as f32 / WAVELENGTH_COUNT as f32).fract();
+            *lambda = sample_visible_wavelength(up);
+            *pdf = visible_wavelength_pdf(*lambda);
+        }
+        SampledWavelengths { lambda, pdf }
+    }
+
+    fn terminate_secondary(&mut self) {
+        if self.pdf[1..].iter().all(|&pdf| pdf == 0.0) {
+            return;
+        }
+        for pdf in &mut self.pdf[1..] {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= WAVELENGTH_COUNT as f32;
+    }
+
+    // Values of an RGB color's smooth spectrum, scaled so colors brighter than white keep their intensity
+    fn upsample(&self, rgb: Vec3) -> Vec3 {
+        let rgb = [rgb.r().max(0.0), rgb.g().max(0.0), rgb.b().max(0.0)];
+        let max = rgb[0].max(rgb[1]).max(rgb[2]);
+        if max <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        // Halving keeps the polynomial away from where the sigmoid saturates
+        let scale = 2.0 * max;
+        let coefficients = tables().coefficients(rgb.map(|c| c / scale));
+        let value = |lambda: f32| scale * sigmoid_polynomial(&coefficients, lambda);
+        Vec3::new(value(self.lambda[0]), value(self.lambda[1]), value(self.lambda[2]))
+    }
+
+    // Values of a color's smooth spectrum fitted without scaling, as pbrt's RGBAlbedoSpectrum does, so the
+    // sigmoid keeps them within [0, 1]
+    fn upsample_albedo(&self, rgb: Vec3) -> Vec3 {
+        let coefficients = tables().coefficients([rgb.r(), rgb.g(), rgb.b()]);
+        let value = |lambda: f32| sigmoid_polynomial(&coefficients, lambda);
+        Vec3::new(value(self.lambda[0]), value(self.lambda[1]), value(self.lambda[2]))
+    }
+
+    // Monte Carlo estimate of the color of a spectrum from its values at these wavelengths
+    fn to_rgb(self, value: Vec3) -> Vec3 {
+        let tables = tables();
+        let values = [value.x(), value.y(), value.z()];
+        let mut xyz = [0.0; 3];
+        for ((&lambda, &pdf), &value) in self.lambda.iter().zip(self.pdf.iter()).zip(values.iter()) {
+            if pdf == 0.0 {
+                continue;
+            }
+            for (xyz, matching) in xyz.iter_mut().zip(cie_xyz(lambda)) {
+                *xyz += value * matching / pdf;
+            }
+        }
+        let xyz = xyz.map(|c| c / (WAVELENGTH_COUNT as f32 * tables.cie_y_integral));
+        let rgb = mat_vec(&tables.xyz_to_rgb, xyz);
+        Vec3::new(rgb[0], rgb[1], rgb[2])
+    }
+}
+
+// Density that follows the eye's sensitivity, from pbrt-v4
+fn visible_wavelength_pdf(lambda: f32) -> f32 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+    0.003_939_804 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+fn sample_visible_wavelength(u: f32) -> f32 {
+    538.0 - 138.888_89 * (0.856_910_6 - 1.827_502 * u).atanh()
+}
+
+// CIE 1931 color matching functions, from the multi-lobe fit of Wyman, Sloan and Shirley, "Simple Analytic
+// Approximations to the CIE XYZ Color Matching Functions" (2013)
+fn cie_xyz(lambda: f32) -> [f32; 3] {
+    let lobe = |mean: f32, below: f32, above: f32| {
+        let t = (lambda - mean) / if lambda < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7) - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
+}
+
+fn sigmoid(x: f32) -> f32 {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+
+// Coefficients are for a quadratic in the wavelength remapped to [0, 1] over the visible range
+fn sigmoid_polynomial(coefficients: &[f32; 3], lambda: f32) -> f32 {
+    let t = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+    sigmoid((coefficients[0] * t + coefficients[1]) * t + coefficients[2])
+}
+
+type Mat3 = [[f32; 3]; 3];
+
+fn mat_vec(m: &Mat3, v: [f32; 3]) -> [f32; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn mat_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut product = [[0.0; 3]; 3];
+    for (row, a_row) in product.iter_mut().zip(a.iter()) {
+        for (column, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a_row[k] * b[k][column]).sum();
+        }
+    }
+    product
+}
+
+// Solves m x = v by Cramer's rule, None if m is singular
+fn solve(m: &[[f64; 3]; 3], v: [f64; 3]) -> Option<[f64; 3]> {
+    let determinant = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = determinant(m);
+    if d.abs() < 1e-15 {
+        return None;
+    }
+    let mut x = [0.0; 3];
+    for (column, x) in x.iter_mut().enumerate() {
+        let mut replaced = *m;
+        for (row, value) in replaced.iter_mut().zip(v.iter()) {
+            row[column] = *value;
+        }
+        *x = determinant(&replaced) / d;
+    }
+    Some(x)
+}
+
+const XYZ_TO_SRGB: Mat3 = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266, 1.876_010_8, 0.041_556],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+const BRADFORD: Mat3 = [[0.8951, 0.2664, -0.1614], [-0.7502, 1.7135, 0.0367], [0.0389, -0.0685, 1.0296]];
+const BRADFORD_INVERSE: Mat3 = [
+    [0.986_992_9, -0.147_054_3, 0.159_962_7],
+    [0.432_305_3, 0.518_360_3, 0.049_291_2],
+    [-0.008_528_7, 0.040_042_8, 0.968_486_7],
+];
+const D65_WHITE: [f32; 3] = [0.950_47, 1.0, 1.088_83];
+
+// Resolution of the coefficient table along each of its axes
+const TABLE_RESOLUTION: usize = 32;
+// Step of the wavelength grid spectra are integrated over while fitting
+const FIT_STEP: f32 = 5.0;
+
+// Built on first use, so RGB renders never pay for fitting the coefficient table
+struct Tables {
+    // XYZ to linear sRGB, with a Bradford adaptation so a constant spectrum comes out white
+    xyz_to_rgb: Mat3,
+    // Integral of the y matching function, which a spectrum's Y is normalized by
+    cie_y_integral: f32,
+    // Brightness of the colors in each z slice of the table
+    z_nodes: [f32; TABLE_RESOLUTION],
+    // Sigmoid polynomial coefficients indexed by the brightest channel, then by its brightness and the
+    // other two channels relative to it; see coefficients()
+    coefficients: Vec<[f32; 3]>,
+}
+
+static TABLES: OnceLock<Tables> = OnceLock::new();
+
+fn tables() -> &'static Tables {
+    TABLES.get_or_init(Tables::new)
+}
+
+impl Tables {
+    fn new() -> Self {
+        let steps = ((LAMBDA_MAX - LAMBDA_MIN) / FIT_STEP) as usize;
+        let wavelengths: Vec<f32> = (0..=steps).map(|i| LAMBDA_MIN + i as f32 * FIT_STEP).collect();
+        let mut white = [0.0; 3];
+        for &lambda in &wavelengths {
+            for (white, matching) in white.iter_mut().zip(cie_xyz(lambda)) {
+                *white += matching * FIT_STEP;
+            }
+        }
+        let cie_y_integral = white[1];
+        let white = white.map(|c| c / cie_y_integral);
+
+        let (source, target) = (mat_vec(&BRADFORD, white), mat_vec(&BRADFORD, D65_WHITE));
+        let scale = [
+            [target[0] / source[0], 0.0, 0.0],
+            [0.0, target[1] / source[1], 0.0],
+            [0.0, 0.0, target[2] / source[2]],
+        ];
+        let adaptation = mat_mul(&BRADFORD_INVERSE, &mat_mul(&scale, &BRADFORD));
+        let xyz_to_rgb = mat_mul(&XYZ_TO_SRGB, &adaptation);
+
+        // How much each grid wavelength contributes to red, green and blue
+        let weights: Vec<(f64, [f64; 3])> = wavelengths
+            .iter()
+            .map(|&lambda| {
+                let rgb = mat_vec(&xyz_to_rgb, cie_xyz(lambda));
+                let t = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+                (t as f64, rgb.map(|c| (c * FIT_STEP / cie_y_integral) as f64))
+            })
+            .collect();
+
+        let resolution = TABLE_RESOLUTION;
+        let mut z_nodes = [0.0; TABLE_RESOLUTION];
+        for (k, node) in z_nodes.iter_mut().enumerate() {
+            // Nodes bunch up near black and white, where the coefficients change fastest
+            let smoothstep = |x: f32| x * x * (3.0 - 2.0 * x);
+            *node = smoothstep(smoothstep(k as f32 / (resolution - 1) as f32));
+        }
+
+        let mut coefficients = vec![[0.0; 3]; 3 * resolution * resolution * resolution];
+        for channel in 0..3 {
+            for j in 0..resolution {
+                for i in 0..resolution {
+                    let (x, y) = (i as f32 / (resolution - 1) as f32, j as f32 / (resolution - 1) as f32);
+                    // Each fit starts from its neighbour's solution, working outwards from a mid grey slice
+                    let start = resolution / 5;
+                    let mut fit_slice = |k: usize, guess: [f64; 3]| {
+                        let z = z_nodes[k];
+                        let mut rgb = [0.0; 3];
+                        rgb[channel] = z;
+                        rgb[(channel + 1) % 3] = x * z;
+                        rgb[(channel + 2) % 3] = y * z;
+                        let fitted = fit(rgb.map(|c| c as f64), guess, &weights);
+                        coefficients[Self::index(channel, k, j, i)] = fitted.map(|c| c as f32);
+                        fitted
+                    };
+                    let mut guess = [0.0; 3];
+                    for k in start..resolution {
+                        guess = fit_slice(k, guess);
+                    }
+                    let mut guess = [0.0; 3];
+                    for k in (0..start).rev() {
+                        guess = fit_slice(k, guess);
+                    }
+                }
+            }
+        }
+
+        Tables {
+            xyz_to_rgb,
+            cie_y_integral,
+            z_nodes,
+            coefficients,
+        }
+    }
+
+    fn index(channel: usize, k: usize, j: usize, i: usize) -> usize {
+        ((channel * TABLE_RESOLUTION + k) * TABLE_RESOLUTION + j) * TABLE_RESOLUTION + i
+    }
+
+    // Coefficients for a color with channels in [0, 1], interpolated from the table
+    fn coefficients(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let rgb = rgb.map(|c| c.clamp(0.0, 1.0));
+        if rgb[0] == rgb[1] && rgb[1] == rgb[2] {
+            // Constant spectra are exact
+            let c = rgb[0];
+            return [0.0, 0.0, (c - 0.5) / (c * (1.0 - c)).sqrt()];
+        }
+        let channel = if rgb[0] > rgb[1] {
+            if rgb[0] > rgb[2] {
+                0
+            } else {
+                2
+            }
+        } else if rgb[1] > rgb[2] {
+            1
+        } else {
+            2
+        };
+        let last = (TABLE_RESOLUTION - 1) as f32;
+        let z = rgb[channel];
+        let x = rgb[(channel + 1) % 3] / z * last;
+        let y = rgb[(channel + 2) % 3] / z * last;
+        let k = self.z_nodes.partition_point(|&node| node <= z).clamp(1, TABLE_RESOLUTION - 1) - 1;
+        let i = (x as usize).min(TABLE_RESOLUTION - 2);
+        let j = (y as usize).min(TABLE_RESOLUTION - 2);
+        let dz = (z - self.z_nodes[k]) / (self.z_nodes[k + 1] - self.z_nodes[k]);
+        let (dx, dy) = (x - i as f32, y - j as f32);
+
+        let mut result = [0.0; 3];
+        for (corner_k, weight_k) in [(k, 1.0 - dz), (k + 1, dz)] {
+            for (corner_j, weight_j) in [(j, 1.0 - dy), (j + 1, dy)] {
+                for (corner_i, weight_i) in [(i, 1.0 - dx), (i + 1, dx)] {
+                    let weight = weight_k * weight_j * weight_i;
+                    let corner = self.coefficients[Self::index(channel, corner_k, corner_j, corner_i)];
+                    for (result, value) in result.iter_mut().zip(corner) {
+                        *result += weight * value;
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+// Gauss-Newton fit of sigmoid polynomial coefficients whose spectrum has the color target
+fn fit(target: [f64; 3], guess: [f64; 3], weights: &[(f64, [f64; 3])]) -> [f64; 3] {
+    let mut coefficients = guess;
+    for _ in 0..15 {
+        let mut residual = target.map(|c| -c);
+        let mut jacobian = [[0.0; 3]; 3];
+        for &(t, weight) in weights {
+            let x = (coefficients[0] * t + coefficients[1]) * t + coefficients[2];
+            let root = (1.0 + x * x).sqrt();
+            let value = 0.5 + x / (2.0 * root);
+            let derivative = 0.5 / (root * root * root);
+            let partials = [derivative * t * t, derivative * t, derivative];
+            for (channel, &weight) in weight.iter().enumerate() {
+                residual[channel] += value * weight;
+                for (entry, partial) in jacobian[channel].iter_mut().zip(partials) {
+                    *entry += partial * weight;
+                }
+            }
+        }
+        if residual.iter().map(|r| r * r).sum::<f64>() < 1e-12 {
+            break;
+        }
+        let step = match solve(&jacobian, residual) {
+            Some(step) => step,
+            None => break,
+        };
+        for (coefficient, step) in coefficients.iter_mut().zip(step) {
+            *coefficient -= step;
+        }
+        // Colors outside the gamut a reflectance can reach send the coefficients off towards infinity
+        let largest = coefficients.iter().fold(0.0f64, |largest, c| largest.max(c.abs()));
+        if largest > 200.0 {
+            coefficients = coefficients.map(|c| c * 200.0 / largest);
+        }
+    }
+    coefficients
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturated_albedos_stay_within_one() {
+        let colors = [
+            Vec3::new(0.9, 0.1, 0.1),
+            Vec3::new(0.1, 0.9, 0.1),
+            Vec3::new(0.1, 0.1, 0.9),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        for lambda in (LAMBDA_MIN as u32..=LAMBDA_MAX as u32).step_by(5) {
+            let lambda = lambda as f32;
+            let wavelengths = SampledWavelengths { lambda: [lambda; WAVELENGTH_COUNT], pdf: [1.0; WAVELENGTH_COUNT] };
+            for color in colors {
+                let value = wavelengths.upsample_albedo(color);
+                for channel in [value.r(), value.g(), value.b()] {
+                    assert!((0.0..=1.0).contains(&channel), "{:?} at {} nm gives {}", color, lambda, channel);
+                }
+            }
+        }
+    }
+}
@@ -1,14 +1,63 @@
-use crate::object::{Object, HitRecord};
+use crate::object::{unless_masked, AlphaMask, Object, HitRecord};
 use crate::vec3::Vec3;
 use crate::ray::Ray;
 use crate::material::Material;
 use crate::bounding_box::BoundingBox;
+use crate::light::Light;
+use crate::utils::gamma;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+// Longitude and latitude of a point on the unit sphere, with v running from the bottom pole to the top
+fn sphere_uv(normal: &Vec3) -> (f32, f32) {
+    let phi = (-normal.z()).atan2(normal.x()) + PI;
+    let theta = (-normal.y()).clamp(-1.0, 1.0).acos();
+    (phi / (2.0 * PI), theta / PI)
+}
+
+// Roots of the ray's distance to center reaching radius, nearest first. Solved as in Numerical Recipes
+// rather than directly so that neither root loses its precision to cancellation.
+fn sphere_roots(ray: &Ray, center: Vec3, radius: f32) -> Option<(f32, f32)> {
+    let oc = ray.origin() - center;
+    let a = ray.direction().dot(&ray.direction());
+    let b = 2.0 * oc.dot(&ray.direction());
+    let c = oc.dot(&oc) - radius * radius;
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant <= 0.0 {
+        return None;
+    }
+    let q = -0.5 * (b + discriminant.sqrt().copysign(b));
+    let (t0, t1) = (q / a, c / q);
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+// Point where a ray hit a sphere, moved back onto the surface from wherever rounding left it along the
+// ray, with the error bound that leaves
+fn sphere_point(ray: &Ray, t: f32, center: Vec3, radius: f32) -> (Vec3, Vec3) {
+    let relative = ray.point_at_parameter(t) - center;
+    let relative = relative.scalar_mul(radius / relative.length());
+    let error = relative.abs().scalar_mul(gamma(5)) + (center.abs() + relative.abs()).scalar_mul(gamma(1));
+    (center + relative, error)
+}
+
+// Derivatives of the point on a sphere of the given radius along u and v, from its coordinates
+fn sphere_tangents(uv: (f32, f32), radius: f32) -> (Vec3, Vec3) {
+    let (phi, theta) = (2.0 * PI * uv.0, PI * uv.1);
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    let dpdu = Vec3::new(sin_theta * sin_phi, 0.0, sin_theta * cos_phi).scalar_mul(2.0 * PI * radius);
+    let dpdv = Vec3::new(-cos_theta * cos_phi, sin_theta, cos_theta * sin_phi).scalar_mul(PI * radius);
+    (dpdu, dpdv)
+}
 
 pub struct Sphere {
     center: Vec3,
     radius: f32,
     material: Box<dyn Material>,
     bbox: BoundingBox,
+    light: Option<Arc<dyn Light>>,
+    alpha_mask: Option<AlphaMask>,
 }
 
 impl Sphere {
@@ -17,31 +66,42 @@ impl Sphere {
             center - Vec3::new(radius, radius, radius), 
             center + Vec3::new(radius, radius, radius)
         );
-        Sphere {center, radius, material, bbox}
+        Sphere {center, radius, material, bbox, light: None, alpha_mask: None}
+    }
+
+    // Marks the sphere as the surface of an area light
+    pub fn with_light(mut self, light: Arc<dyn Light>) -> Self {
+        self.light = Some(light);
+        self
+    }
+
+    pub fn with_alpha_mask(mut self, alpha_mask: AlphaMask) -> Self {
+        self.alpha_mask = Some(alpha_mask);
+        self
+    }
+
+    fn hit_record(&self, ray: &Ray, t: f32) -> HitRecord<'_> {
+        let (point, error) = sphere_point(ray, t, self.center, self.radius);
+        let normal = (point - self.center).scalar_mul(1.0 / self.radius);
+        let mut hit_record = HitRecord::new(ray, t, point, normal, self.material.as_ref());
+        hit_record.error = error;
+        hit_record.uv = sphere_uv(&normal);
+        (hit_record.dpdu, hit_record.dpdv) = sphere_tangents(hit_record.uv, self.radius);
+        hit_record.light = self.light.as_deref();
+        hit_record
     }
 }
 
 impl Object for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = ray.origin() - self.center;
-        let a = ray.direction().dot(&ray.direction());
-        let b = 2.0 * oc.dot(&ray.direction());
-        let c = oc.dot(&oc) - self.radius * self.radius;
-
-        let discriminant = b * b - 4.0 * a * c;
-        if discriminant > 0.0 {
-            let t = (-b - discriminant.sqrt()) / (2.0 * a);
-            if t < t_max && t > t_min {
-                let point = ray.point_at_parameter(t);
-                let normal = (point - self.center).scalar_mul(1.0 / self.radius);
-                return Some(HitRecord::new(t, point, normal, self.material.as_ref()))
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t0, t1) = sphere_roots(ray, self.center, self.radius)?;
+        if t0 < t_max && t0 > t_min {
+            if let Some(hit_record) = unless_masked(self.alpha_mask.as_ref(), ray, self.hit_record(ray, t0)) {
+                return Some(hit_record)
             }
-            let t = (-b + discriminant.sqrt()) / (2.0 * a);
-            if t < t_max && t > t_min {
-                let point = ray.point_at_parameter(t);
-                let normal = (point - self.center).scalar_mul(1.0 / self.radius);
-                return Some(HitRecord::new(t, point, normal, self.material.as_ref()))
-            } 
+        }
+        if t1 < t_max && t1 > t_min {
+            return unless_masked(self.alpha_mask.as_ref(), ray, self.hit_record(ray, t1))
         }
         None
     }
@@ -59,6 +119,7 @@ pub struct MovingSphere {
     end_time: f32,
     material: Box<dyn Material>,
     bbox: BoundingBox,
+    alpha_mask: Option<AlphaMask>,
 }
 
 
@@ -74,36 +135,41 @@ impl MovingSphere {
                 end_center + Vec3::new(radius, radius, radius)
             ),
         );
-        MovingSphere {start_center, end_center, radius, start_time, end_time, material, bbox}
+        MovingSphere {start_center, end_center, radius, start_time, end_time, material, bbox, alpha_mask: None}
+    }
+
+    pub fn with_alpha_mask(mut self, alpha_mask: AlphaMask) -> Self {
+        self.alpha_mask = Some(alpha_mask);
+        self
     }
 
     #[inline]
     fn center(&self, time: f32) -> Vec3 {
         self.start_center + (self.end_center - self.start_center).scalar_mul( (time - self.start_time) / (self.end_time - self.start_time))
     }
+
+    fn hit_record(&self, ray: &Ray, t: f32) -> HitRecord<'_> {
+        let center = self.center(ray.time());
+        let (point, error) = sphere_point(ray, t, center, self.radius);
+        let normal = (point - center).scalar_mul(1.0 / self.radius);
+        let mut hit_record = HitRecord::new(ray, t, point, normal, self.material.as_ref());
+        hit_record.error = error;
+        hit_record.uv = sphere_uv(&normal);
+        (hit_record.dpdu, hit_record.dpdv) = sphere_tangents(hit_record.uv, self.radius);
+        hit_record
+    }
 }
 
 impl Object for MovingSphere {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = ray.origin() - self.center(ray.time());
-        let a = ray.direction().dot(&ray.direction());
-        let b = 2.0 * oc.dot(&ray.direction());
-        let c = oc.dot(&oc) - self.radius * self.radius;
-
-        let discriminant = b * b - 4.0 * a * c;
-        if discriminant > 0.0 {
-            let t = (-b - discriminant.sqrt()) / (2.0 * a);
-            if t < t_max && t > t_min {
-                let point = ray.point_at_parameter(t);
-                let normal = (point - self.center(ray.time())).scalar_mul(1.0 / self.radius);
-                return Some(HitRecord::new(t, point, normal, self.material.as_ref()))
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t0, t1) = sphere_roots(ray, self.center(ray.time()), self.radius)?;
+        if t0 < t_max && t0 > t_min {
+            if let Some(hit_record) = unless_masked(self.alpha_mask.as_ref(), ray, self.hit_record(ray, t0)) {
+                return Some(hit_record)
             }
-            let t = (-b + discriminant.sqrt()) / (2.0 * a);
-            if t < t_max && t > t_min {
-                let point = ray.point_at_parameter(t);
-                let normal = (point - self.center(ray.time())).scalar_mul(1.0 / self.radius);
-                return Some(HitRecord::new(t, point, normal, self.material.as_ref()))
-            } 
+        }
+        if t1 < t_max && t1 > t_min {
+            return unless_masked(self.alpha_mask.as_ref(), ray, self.hit_record(ray, t1))
         }
         None
     }
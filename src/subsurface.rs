@@ -0,0 +1,141 @@
+// Translucent material like skin, wax or marble. Light entering through the surface random walks through
+// the scattering medium inside until it reaches the surface again. The surface is a dielectric interface,
+// smooth or rough, that reflects what doesn't enter; what does crosses it diffusely both ways, as if the
+// interface were rough on the scale of a mean free path. Leaving diffusely means lights can be sampled
+// where paths leave, rather than having to be found through a specular interface by chance.
+use crate::{
+    material::{BsdfSample, Material},
+    medium::Medium,
+    microfacet::{fresnel_dielectric, reflect_about, TrowbridgeReitz},
+    object::HitRecord,
+    onb::Onb,
+    sampler::Sampler,
+    utils::cosine_sample_hemisphere,
+    vec3::Vec3,
+};
+
+use std::f32::consts::PI;
+
+pub struct Subsurface {
+    medium: Medium,
+    refractive_index: f32,
+    distribution: TrowbridgeReitz,
+}
+
+impl Subsurface {
+    // Diffuse albedo and mean free path inside, for each of red, green and blue
+    pub fn new(albedo: Vec3, mean_free_path: Vec3) -> Self {
+        Subsurface {
+            medium: Medium::subsurface(albedo, mean_free_path),
+            refractive_index: 1.4,
+            distribution: TrowbridgeReitz::new(0.0, 0.0),
+        }
+    }
+
+    pub fn with_refractive_index(mut self, refractive_index: f32) -> Self {
+        self.refractive_index = refractive_index;
+        self
+    }
+
+    // GGX alpha of the surface's reflection along each tangent of the shading frame
+    pub fn with_roughness(mut self, roughness_u: f32, roughness_v: f32) -> Self {
+        self.distribution = TrowbridgeReitz::new(roughness_u, roughness_v);
+        self
+    }
+
+    // Frame around the outward normal, so local directions with negative z are inside
+    fn frame(hit_record: &HitRecord) -> Onb {
+        hit_record.shading_frame()
+    }
+
+    // Whether light reaching the surface has come through the medium
+    fn leaving(hit_record: &HitRecord) -> bool {
+        !hit_record.front_face
+    }
+
+    fn reflection_eval(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if self.distribution.effectively_smooth() || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wh = (*wo + *wi).normalize();
+        let fresnel = fresnel_dielectric(wo.dot(&wh), self.refractive_index);
+        self.distribution.d(&wh) * self.distribution.g(wo, wi) * fresnel / (4.0 * wo.z())
+    }
+
+    fn reflection_pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        if self.distribution.effectively_smooth() || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wh = (*wo + *wi).normalize();
+        self.distribution.visible_d(wo, &wh) / (4.0 * wo.dot(&wh).abs())
+    }
+}
+
+impl Material for Subsurface {
+    fn eval(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let leaving = Self::leaving(hit_record);
+        let frame = Self::frame(hit_record);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let f = if leaving {
+            wi.z().max(0.0) / PI
+        } else if wi.z() > 0.0 {
+            self.reflection_eval(&wo, &wi)
+        } else {
+            (1.0 - fresnel_dielectric(wo.z(), self.refractive_index)) * -wi.z() / PI
+        };
+        Vec3::new(f, f, f)
+    }
+
+    fn sample(&self, hit_record: &HitRecord, wo: &Vec3, sampler: &mut dyn Sampler) -> Option<BsdfSample> {
+        let uc = sampler.get_1d();
+        let u = sampler.get_2d();
+        let frame = Self::frame(hit_record);
+        let wo_local = frame.to_local(wo);
+
+        let wi_local = if Self::leaving(hit_record) {
+            cosine_sample_hemisphere(u)
+        } else {
+            let reflectance = fresnel_dielectric(wo_local.z(), self.refractive_index);
+            if uc < reflectance {
+                if self.distribution.effectively_smooth() {
+                    let wi = Vec3::new(-wo_local.x(), -wo_local.y(), wo_local.z());
+                    return Some(BsdfSample {
+                        wi: frame.local(&wi),
+                        f: Vec3::new(reflectance, reflectance, reflectance),
+                        pdf: reflectance,
+                        is_specular: true,
+                    });
+                }
+                reflect_about(&wo_local, &self.distribution.sample_wm(&wo_local, u))
+            } else {
+                -cosine_sample_hemisphere(u)
+            }
+        };
+        let wi = frame.local(&wi_local);
+        let pdf = self.pdf(hit_record, wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(hit_record, wo, &wi),
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, hit_record: &HitRecord, wo: &Vec3, wi: &Vec3) -> f32 {
+        let leaving = Self::leaving(hit_record);
+        let frame = Self::frame(hit_record);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if leaving {
+            return wi.z().max(0.0) / PI;
+        }
+        let reflectance = fresnel_dielectric(wo.z(), self.refractive_index);
+        reflectance * self.reflection_pdf(&wo, &wi) + (1.0 - reflectance) * (-wi.z()).max(0.0) / PI
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        Some(&self.medium)
+    }
+}
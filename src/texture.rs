@@ -0,0 +1,190 @@
+// Values that vary over a surface, looked up from the hit. Material parameters that are plain numbers use
+// a texture's value through scalar().
+use crate::{object::HitRecord, vec3::Vec3};
+use std::sync::Arc;
+
+pub trait Texture: Sync + Send {
+    fn value(&self, hit_record: &HitRecord) -> Vec3;
+
+    // Mean of the channels, so a grey texture gives its grey level
+    fn scalar(&self, hit_record: &HitRecord) -> f32 {
+        let value = self.value(hit_record);
+        (value.r() + value.g() + value.b()) / 3.0
+    }
+}
+
+pub struct Constant {
+    value: Vec3,
+}
+
+impl Constant {
+    pub fn new(value: Vec3) -> Self {
+        Constant { value }
+    }
+
+    // The same value in every channel
+    pub fn scalar(value: f32) -> Self {
+        Constant::new(Vec3::new(value, value, value))
+    }
+}
+
+impl Texture for Constant {
+    fn value(&self, _hit_record: &HitRecord) -> Vec3 {
+        self.value
+    }
+}
+
+// Alternates between two textures in squares over the surface's uv coordinates, scale squares along each
+pub struct Checker {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    scale: f32,
+}
+
+impl Checker {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f32) -> Self {
+        Checker { even, odd, scale }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, hit_record: &HitRecord) -> Vec3 {
+        let (u, v) = hit_record.uv;
+        let square = (u * self.scale).floor() as i64 + (v * self.scale).floor() as i64;
+        if square.rem_euclid(2) == 0 {
+            self.even.value(hit_record)
+        } else {
+            self.odd.value(hit_record)
+        }
+    }
+}
+
+// How an image file's values relate to linear ones. Colors are usually sRGB encoded, while data such as
+// normal maps is stored as is.
+#[derive(Clone, Copy, Debug)]
+pub enum ColorEncoding {
+    Srgb,
+    Linear,
+}
+
+impl std::str::FromStr for ColorEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(ColorEncoding::Srgb),
+            "linear" => Ok(ColorEncoding::Linear),
+            _ => Err(format!("Unknown color encoding '{}'", s)),
+        }
+    }
+}
+
+// Image mapped over the surface's uv coordinates, repeating outside [0, 1] and bilinearly filtered.
+// Pixels are stored linear.
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+impl Image {
+    // Reads a binary (P6) or plain (P3) PPM
+    pub fn parse_ppm(data: &[u8], encoding: ColorEncoding) -> Result<Self, String> {
+        let mut position = 0;
+        // Header fields are separated by whitespace and may be interleaved with '#' comments
+        let mut next_field = || -> Result<String, String> {
+            loop {
+                while position < data.len() && data[position].is_ascii_whitespace() {
+                    position += 1;
+                }
+                if position < data.len() && data[position] == b'#' {
+                    while position < data.len() && data[position] != b'\n' {
+                        position += 1;
+                    }
+                    continue;
+                }
+                break;
+            }
+            let start = position;
+            while position < data.len() && !data[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if start == position {
+                return Err("Unexpected end of image".to_string());
+            }
+            Ok(String::from_utf8_lossy(&data[start..position]).into_owned())
+        };
+        let number = |field: String| field.parse::<usize>().map_err(|_| format!("Invalid number '{}'", field));
+
+        let magic = next_field()?;
+        let width = number(next_field()?)?;
+        let height = number(next_field()?)?;
+        let max_value = number(next_field()?)?;
+        if width == 0 || height == 0 || max_value == 0 || max_value > 65535 {
+            return Err("Invalid image header".to_string());
+        }
+        let count = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(3))
+            .ok_or_else(|| "Invalid image header".to_string())?;
+        let values: Vec<usize> = match magic.as_str() {
+            "P3" => (0..count).map(|_| next_field().and_then(number)).collect::<Result<_, _>>()?,
+            "P6" => {
+                // A single whitespace byte separates the header from the samples
+                let start = position + 1;
+                let bytes = if max_value < 256 { 1 } else { 2 };
+                let samples = count
+                    .checked_mul(bytes)
+                    .and_then(|length| data.get(start..start.checked_add(length)?))
+                    .ok_or_else(|| "Unexpected end of image".to_string())?;
+                if bytes == 1 {
+                    samples.iter().map(|&sample| sample as usize).collect()
+                } else {
+                    samples.chunks(2).map(|pair| (pair[0] as usize) << 8 | pair[1] as usize).collect()
+                }
+            }
+            _ => return Err(format!("Unsupported image format '{}'", magic)),
+        };
+        let decode = |value: usize| {
+            let value = value.min(max_value) as f32 / max_value as f32;
+            match encoding {
+                ColorEncoding::Srgb => srgb_to_linear(value),
+                ColorEncoding::Linear => value,
+            }
+        };
+        let pixels = values
+            .chunks(3)
+            .map(|rgb| Vec3::new(decode(rgb[0]), decode(rgb[1]), decode(rgb[2])))
+            .collect();
+        Ok(Image { width, height, pixels })
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> Vec3 {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for Image {
+    fn value(&self, hit_record: &HitRecord) -> Vec3 {
+        let (u, v) = hit_record.uv;
+        // Rows run top down while v runs up
+        let x = u * self.width as f32 - 0.5;
+        let y = (1.0 - v) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.pixel(x0, y0).lerp(&self.pixel(x0 + 1, y0), dx);
+        let bottom = self.pixel(x0, y0 + 1).lerp(&self.pixel(x0 + 1, y0 + 1), dx);
+        top.lerp(&bottom, dy)
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -0,0 +1,136 @@
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn new(x0: usize, y0: usize, x1: usize, y1: usize) -> Self {
+        Tile { x0, y0, x1, y1 }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> {
+        let (x0, x1) = (self.x0, self.x1);
+        (self.y0..self.y1).flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+    }
+}
+
+// The order in which tiles are handed out to the render threads
+#[derive(Clone, Copy, Debug)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    Hilbert,
+}
+
+// Splits a width x height image into tiles of at most tile_size x tile_size pixels, with row 0 at the top
+pub fn generate_tiles(width: usize, height: usize, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+
+    let coords = match order {
+        TileOrder::Scanline => scanline_order(tiles_x, tiles_y),
+        TileOrder::Spiral => spiral_order(tiles_x, tiles_y),
+        TileOrder::Hilbert => hilbert_order(tiles_x, tiles_y),
+    };
+
+    coords
+        .into_iter()
+        .map(|(tx, ty)| {
+            let x0 = tx * tile_size;
+            let y0 = ty * tile_size;
+            Tile::new(x0, y0, (x0 + tile_size).min(width), (y0 + tile_size).min(height))
+        })
+        .collect()
+}
+
+fn scanline_order(tiles_x: usize, tiles_y: usize) -> Vec<(usize, usize)> {
+    (0..tiles_y).flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty))).collect()
+}
+
+// Walks a square spiral outwards from the center tile, skipping positions outside the grid
+fn spiral_order(tiles_x: usize, tiles_y: usize) -> Vec<(usize, usize)> {
+    let total = tiles_x * tiles_y;
+    let mut coords = Vec::with_capacity(total);
+    let (mut x, mut y) = (((tiles_x as i64) - 1) / 2, ((tiles_y as i64) - 1) / 2);
+    let (mut dx, mut dy) = (1i64, 0i64);
+    let mut leg_length = 1;
+    let mut leg_progress = 0;
+    let mut legs_done = 0;
+    while coords.len() < total {
+        if x >= 0 && y >= 0 && (x as usize) < tiles_x && (y as usize) < tiles_y {
+            coords.push((x as usize, y as usize));
+        }
+        x += dx;
+        y += dy;
+        leg_progress += 1;
+        if leg_progress == leg_length {
+            leg_progress = 0;
+            let (new_dx, new_dy) = (-dy, dx);
+            dx = new_dx;
+            dy = new_dy;
+            legs_done += 1;
+            if legs_done % 2 == 0 {
+                leg_length += 1;
+            }
+        }
+    }
+    coords
+}
+
+// Follows a Hilbert curve over the smallest power of two square covering the grid
+fn hilbert_order(tiles_x: usize, tiles_y: usize) -> Vec<(usize, usize)> {
+    let n = tiles_x.max(tiles_y).next_power_of_two();
+    (0..n * n)
+        .map(|d| hilbert_d_to_xy(n, d))
+        .filter(|&(x, y)| x < tiles_x && y < tiles_y)
+        .collect()
+}
+
+fn hilbert_d_to_xy(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+impl std::str::FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("Unknown tile order '{}'", s)),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    bounding_box::BoundingBox,
+    light::Light,
+    material::Material,
+    object::{HitRecord, Object},
+    ray::Ray,
+    utils::gamma,
+    vec3::Vec3,
+};
+use std::sync::Arc;
+
+// Indexed triangles sharing one material. Each triangle's front faces the side its vertices wind
+// counterclockwise around.
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    indices: Vec<[usize; 3]>,
+    material: Box<dyn Material>,
+    light: Option<Arc<dyn Light>>,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Vec3>, indices: Vec<[usize; 3]>, material: Box<dyn Material>) -> Self {
+        TriangleMesh {
+            positions,
+            indices,
+            material,
+            light: None,
+        }
+    }
+
+    // Marks the mesh as the surface of an area light
+    pub fn with_light(mut self, light: Arc<dyn Light>) -> Self {
+        self.light = Some(light);
+        self
+    }
+
+    // One object per triangle, so the scene's BVH is built over the individual triangles
+    pub fn triangles(self: &Arc<Self>) -> Vec<Box<dyn Object>> {
+        (0..self.indices.len())
+            .map(|index| Box::new(Triangle::new(self.clone(), index)) as Box<dyn Object>)
+            .collect()
+    }
+}
+
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    index: usize,
+    bbox: BoundingBox,
+}
+
+impl Triangle {
+    fn new(mesh: Arc<TriangleMesh>, index: usize) -> Self {
+        let bbox = BoundingBox::from_points(&mesh.indices[index].map(|vertex| mesh.positions[vertex]));
+        Triangle { mesh, index, bbox }
+    }
+
+    fn vertices(&self) -> [Vec3; 3] {
+        self.mesh.indices[self.index].map(|vertex| self.mesh.positions[vertex])
+    }
+}
+
+impl Object for Triangle {
+    // Moller-Trumbore intersection
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let [p0, p1, p2] = self.vertices();
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let p = ray.direction().cross(&edge2);
+        let determinant = edge1.dot(&p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inv_determinant = 1.0 / determinant;
+        let s = ray.origin() - p0;
+        let b1 = s.dot(&p) * inv_determinant;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let q = s.cross(&edge1);
+        let b2 = ray.direction().dot(&q) * inv_determinant;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(&q) * inv_determinant;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+        let normal = edge1.cross(&edge2).normalize();
+        // Rebuilt from the barycentric coordinates, which bounds its error independently of how far the ray came
+        let (w0, w1, w2) = (p0.scalar_mul(1.0 - b1 - b2), p1.scalar_mul(b1), p2.scalar_mul(b2));
+        let mut hit_record = HitRecord::new(ray, t, w0 + w1 + w2, normal, self.mesh.material.as_ref());
+        hit_record.error = (w0.abs() + w1.abs() + w2.abs()).scalar_mul(gamma(7));
+        // Barycentric coordinates, as meshes don't carry texture coordinates
+        hit_record.uv = (b1, b2);
+        hit_record.dpdu = edge1;
+        hit_record.dpdv = edge2;
+        hit_record.light = self.mesh.light.as_deref();
+        Some(hit_record)
+    }
+
+    fn bounding_box(&self) -> Option<&BoundingBox> {
+        Some(&self.bbox)
+    }
+}
@@ -1,47 +1,50 @@
-use crate::vec3::Vec3;
-use rand::{prelude::thread_rng, Rng};
+use crate::{sampler::Sampler, vec3::Vec3};
 
-pub fn random_in_unit_sphere() -> Vec3 {
-    let mut rng = thread_rng();
-    loop {
-        let p =
-            Vec3::new(rng.gen(), rng.gen(), rng.gen()).scalar_mul(2.0) - Vec3::new(1.0, 1.0, 1.0);
-        if p.squared_length() < 1.0 {
-            return p
-        }
-    }
+use std::f32::consts::PI;
+
+// Bound on the relative rounding error of n floating point operations in a row, after Higham 2002
+pub fn gamma(n: u32) -> f32 {
+    let n_epsilon = n as f32 * f32::EPSILON * 0.5;
+    n_epsilon / (1.0 - n_epsilon)
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    let mut rng = thread_rng();
-    loop {
-        let p = Vec3::new(rng.gen(), rng.gen(), 0.0).scalar_mul(2.0) - Vec3::new(1.0, 1.0, 0.0);
-        if p.dot(&p) < 1.0 {
-            return p
-        }
-    }
+// Uniformly distributed unit vector, with density 1 / (4 pi)
+pub fn uniform_sample_sphere(u: (f32, f32)) -> Vec3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn reflect(vector: &Vec3, normal: &Vec3) -> Vec3 {
-    *vector - normal.scalar_mul(2.0 * vector.dot(normal))
+// Maps a fixed number of sample dimensions to a point rather than using rejection sampling, so the
+// stratification of low discrepancy samplers carries through
+pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+    sample_unit_disk(sampler.get_2d())
 }
 
-pub fn refract(vector: &Vec3, normal: &Vec3, ni_over_nt: f32) -> Option<Vec3> {
-    let unit_vector = vector.normalize();
-    let dot_product = unit_vector.dot(normal);
-    let discriminant = 1.0 - ni_over_nt * ni_over_nt * (1.0 - dot_product * dot_product);
-    if discriminant > 0.0 {
-        Some(
-            (unit_vector - normal.scalar_mul(dot_product)).scalar_mul(ni_over_nt)
-                - normal.scalar_mul(discriminant.sqrt()),
-        )
-    } else {
-        None
+// Shirley-Chiu concentric mapping from the unit square to the unit disk
+pub fn sample_unit_disk(u: (f32, f32)) -> Vec3 {
+    let (ox, oy) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if ox == 0.0 && oy == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
     }
+    let (r, theta) = if ox.abs() > oy.abs() {
+        (ox, PI / 4.0 * (oy / ox))
+    } else {
+        (oy, PI / 2.0 - PI / 4.0 * (ox / oy))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+// Direction in the z up hemisphere with density cos(theta) / pi, by projecting up from the unit disk
+pub fn cosine_sample_hemisphere(u: (f32, f32)) -> Vec3 {
+    let d = sample_unit_disk(u);
+    let z = (1.0 - d.x() * d.x() - d.y() * d.y()).max(0.0).sqrt();
+    Vec3::new(d.x(), d.y(), z)
 }
 
-pub fn schlick(cosine: f32, refractive_index: f32) -> f32 {
-    let r0 = (1.0 - refractive_index) / (1.0 + refractive_index);
-    let r0 = r0 * r0;
-    r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
+// Uniformly distributed barycentric coordinates (b0, b1) of a point in a triangle
+pub fn uniform_sample_triangle(u: (f32, f32)) -> (f32, f32) {
+    let su0 = u.0.sqrt();
+    (1.0 - su0, u.1 * su0)
 }
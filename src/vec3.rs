@@ -126,6 +126,21 @@ impl Vec3 {
         )
     }
 
+    #[inline]
+    pub fn abs(&self) -> Vec3 {
+        Vec3::new(self.x().abs(), self.y().abs(), self.z().abs())
+    }
+
+    #[inline]
+    pub fn max_component(&self) -> f32 {
+        self.data[0].max(self.data[1]).max(self.data[2])
+    }
+
+    #[inline]
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.data[0] + 0.7152 * self.data[1] + 0.0722 * self.data[2]
+    }
+
     #[inline]
     pub fn lerp(&self, other: &Vec3, t: f32) -> Vec3 {
         self.scalar_mul(1.0 - t) + other.scalar_mul(t)